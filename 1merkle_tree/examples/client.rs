@@ -1,16 +1,13 @@
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
     commitment_config::CommitmentConfig,
-    instruction::{AccountMeta, Instruction},
-    pubkey::Pubkey,
     signature::{read_keypair_file, Keypair, Signer},
-    system_program,
     transaction::Transaction,
 };
 
-use merkle_tree_program::instructions::CounterInstruction;
+use merkle_tree_program::client;
 use merkle_tree_program::merkle_tree;
-use merkle_tree_program::state::CounterAccount;
+use merkle_tree_program::state::TreeAccount;
 
 #[tokio::main]
 async fn main() {
@@ -40,26 +37,11 @@ async fn main() {
 
     // let counter_keypair = Keypair::new();
 
-    let (counter_keypair_pub, bump_seed) =
-        Pubkey::find_program_address(&[b"vault", payer.pubkey().as_ref()], &program_id);
+    let counter_keypair_pub = client::vault_address(&program_id, &payer.pubkey(), 0);
 
     println!("Testing init...");
 
-    // let init_val = 10i64;
-    let init_val: i64 = bump_seed.into();
-
-    let data = CounterInstruction::InitCounter(bump_seed);
-
-    let init_instruction = Instruction::new_with_borsh(
-        program_id,
-        &data,
-        vec![
-            // AccountMeta::new(counter_keypair_pub, true),
-            AccountMeta::new(counter_keypair_pub, false),
-            AccountMeta::new(payer.pubkey(), true),
-            AccountMeta::new_readonly(system_program::id(), false),
-        ],
-    );
+    let init_instruction = client::init_tree_ix(&program_id, &payer.pubkey(), 0);
 
     // Add the instruction to new transaction
     let mut tx = Transaction::new_with_payer(&[init_instruction], Some(&payer.pubkey()));
@@ -78,25 +60,16 @@ async fn main() {
             panic!("{}", err)
         }
     };
-    let counter =
-        solana_program::borsh1::try_from_slice_unchecked::<CounterAccount>(&account.data).unwrap();
-    assert_eq!(counter.count, init_val);
-    println!("counter init successfully with value {}", counter.count);
+    let (tree_data, _) = TreeAccount::load(&account.data).unwrap();
+    assert_eq!(tree_data.root_hash, merkle_tree::EMPTY_ROOT);
+    println!("tree init successfully with root {}", tree_data.root_hash);
 
     println!("Testing AddLeaf..");
 
-    let data = CounterInstruction::AddLeaf("Test-string1".to_string());
+    let init_str = "Test-string1";
 
-    let init_instruction = Instruction::new_with_borsh(
-        program_id,
-        &data,
-        vec![
-            // AccountMeta::new(counter_keypair_pub, true),
-            AccountMeta::new(counter_keypair_pub, false),
-            AccountMeta::new(payer.pubkey(), true),
-            AccountMeta::new_readonly(system_program::id(), false),
-        ],
-    );
+    let init_instruction =
+        client::add_leaf_ix(&program_id, &counter_keypair_pub, &payer.pubkey(), init_str);
 
     // Add the instruction to new transaction
     let mut tx = Transaction::new_with_payer(&[init_instruction], Some(&payer.pubkey()));
@@ -115,19 +88,17 @@ async fn main() {
             panic!("{}", err)
         }
     };
-    let mut counter_data =
-        solana_program::borsh1::try_from_slice_unchecked::<CounterAccount>(&account.data).unwrap();
+    let (counter_data, leafs) = TreeAccount::load(&account.data).unwrap();
+    let mut leafs = leafs.to_vec();
 
-    if let CounterInstruction::AddLeaf(init_str) = data {
-        println!("root_hash {}", counter_data.root_hash);
-        println!("leafs {:?}", counter_data.leafs);
+    println!("root_hash {}", counter_data.root_hash);
+    println!("leafs {:?}", leafs);
 
-        counter_data.leafs.pop();
-        counter_data.leafs.push(init_str);
+    leafs.pop();
+    leafs.push(merkle_tree::leaf_hash(init_str));
 
-        let tree = merkle_tree::MerkleTree::new(&counter_data.leafs);
+    let tree = merkle_tree::MerkleTree::from_leaf_hashes(&leafs);
 
-        let new_hash = *tree.get_root().unwrap();
-        assert_eq!(counter_data.root_hash, new_hash);
-    }
+    let new_hash = *tree.get_root().unwrap();
+    assert_eq!(counter_data.root_hash, new_hash);
 }
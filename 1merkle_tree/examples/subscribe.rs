@@ -1,28 +1,67 @@
-use solana_client::rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter};
-use solana_sdk::signature::{read_keypair_file, Signer};
+use std::str::FromStr;
 
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter},
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Signer},
+};
+
+use merkle_tree_program::indexer::Indexer;
+
+// Usage: subscribe <vault> [leaves file]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let keypair = read_keypair_file("./target/deploy/merkle_tree_program-keypair.json").unwrap();
-    let program_id = keypair.pubkey().to_string();
+    let program_id = keypair.pubkey();
+
+    let mut args = std::env::args().skip(1);
+    let vault = Pubkey::from_str(&args.next().expect("missing vault address"))?;
+    let path = args.next().unwrap_or_else(|| format!("{}.leaves", vault));
+
+    // Start from the leaves saved by a previous run, or from the account itself
+    let mut indexer = Indexer::open(program_id, vault, &path)?;
+    if indexer.leaves().is_empty() {
+        let client = RpcClient::new_with_commitment(
+            String::from("http://127.0.0.1:8899"),
+            CommitmentConfig::confirmed(),
+        );
+        let account = client.get_account(&vault)?;
+        indexer = Indexer::from_account_data(program_id, &account.data)?.with_path(&path);
+        assert_eq!(indexer.tree_address(), &vault, "not the canonical vault of its tree");
+        indexer.save()?;
+    }
 
     // Connect to the Solana devnet
     let rpc_url = String::from("ws://127.0.0.1:8900");
 
     let (mut sub, rx) = solana_client::pubsub_client::PubsubClient::logs_subscribe(
         rpc_url.as_str(),
-        // mentions: [ <string> ] - array containing a single Pubkey (as base-58 encoded string); 
-        //if present, subscribe to only transactions mentioning this address
-        RpcTransactionLogsFilter::Mentions(vec![program_id]),
-        RpcTransactionLogsConfig { commitment: None },
+        // only transactions mentioning the vault, the indexer skips other trees anyway
+        RpcTransactionLogsFilter::Mentions(vec![vault.to_string()]),
+        RpcTransactionLogsConfig {
+            commitment: Some(CommitmentConfig::confirmed()),
+        },
     )?;
 
-    println!("Subscriber started...");
+    println!("Subscriber started with {} leaves...", indexer.leaves().len());
 
     while let Ok(msg) = rx.recv() {
-        println!("{:?}", msg);
-        // for l in msg.value.logs {
-        //     println!("{}", l);
-        // }
+        // a failed transaction is rolled back, along with its events
+        if msg.value.err.is_some() {
+            continue;
+        }
+        if indexer.process_logs(&msg.value.logs)? > 0 {
+            indexer.save()?;
+            println!(
+                "{}: {} leaves, root {}",
+                msg.value.signature,
+                indexer.leaves().len(),
+                indexer.root()
+            );
+        }
     }
 
     sub.shutdown().unwrap();
@@ -0,0 +1,590 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    entrypoint::MAX_PERMITTED_DATA_INCREASE,
+    hash::Hash,
+    instruction::{Instruction, InstructionError},
+    message::Message,
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signature, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use merkle_tree_program::{
+    client,
+    error::CustomError,
+    merkle_tree::{leaf_hash, Blake3Hasher, Keccak256Hasher, MerkleTree, EMPTY_ROOT},
+    proofs::{build_proofs, build_proofs_with_hasher, read_leaves, HashEncoding, LeafFormat},
+    state::{TreeAccount, DEFAULT_ACCOUNT_SPACE},
+};
+
+/// Leaves sent per `AddLeaves` transaction at most, so that a batch of short leaves
+/// stays well within the compute budget.
+const MAX_BATCH_LEAVES: usize = 64;
+
+#[derive(Parser)]
+#[command(
+    name = "merkle-cli",
+    about = "Manage Merkle trees of the native program"
+)]
+struct Cli {
+    /// RPC endpoint of the cluster
+    #[arg(long, global = true, default_value = "http://127.0.0.1:8899")]
+    url: String,
+    /// Keypair that pays and signs as the tree authority
+    #[arg(long, global = true, default_value = "~/.config/solana/id.json")]
+    keypair: String,
+    /// Address of the deployed program, defaults to the one in target/deploy
+    #[arg(long, global = true)]
+    program_id: Option<Pubkey>,
+    /// Tree id of the vault, together with the keypair it gives the vault address
+    #[arg(long, global = true, default_value_t = 0)]
+    tree_id: u64,
+    /// Vault address, for trees created by another payer
+    #[arg(long, global = true)]
+    vault: Option<Pubkey>,
+    #[arg(long, global = true, value_enum, default_value_t = Output::Text)]
+    output: Output,
+    /// Leaves are hex encoded bytes instead of text
+    #[arg(long, global = true)]
+    hex: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
+/// Hash function of an offline tree, the program only verifies `sha256` trees
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum HashFunction {
+    Sha256,
+    Keccak256,
+    Blake3,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the vault of the tree
+    Init {
+        /// Number of leaves the account has room for
+        #[arg(long)]
+        capacity: Option<u64>,
+    },
+    /// Append a single leaf
+    AddLeaf { leaf: String },
+    /// Append every leaf of a file, in as few transactions as fit. The file is read
+    /// like the input of `build-proofs`, so both give the same root
+    AddLeaves {
+        #[arg(long)]
+        file: PathBuf,
+        /// csv, json or lines, guessed from the extension of the file if not given
+        #[arg(long)]
+        format: Option<LeafFormat>,
+        /// CSV column or JSON field holding the leaf, the first column by default
+        #[arg(long)]
+        column: Option<String>,
+    },
+    /// Reallocate the account to hold `capacity` leaves
+    Resize { capacity: u64 },
+    /// Print the account state
+    Show,
+    /// Print the proof of the leaf at `index`
+    Proof { index: u64 },
+    /// Check a leaf against the stored root with the on-chain verifier
+    Verify {
+        index: u64,
+        leaf: String,
+        /// Comma separated sibling hashes, computed from the account if not given
+        #[arg(long, value_delimiter = ',')]
+        proof: Option<Vec<Hash>>,
+    },
+    /// Dump every leaf hash and the root
+    Export {
+        /// Write to a file instead of stdout
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Build the tree of a leaf file offline and write the proof of every leaf
+    BuildProofs {
+        input: PathBuf,
+        /// csv, json or lines, guessed from the extension of the input if not given
+        #[arg(long)]
+        format: Option<LeafFormat>,
+        /// CSV column or JSON field holding the leaf, the first column by default
+        #[arg(long)]
+        column: Option<String>,
+        #[arg(long, default_value = "proofs.json")]
+        out: PathBuf,
+        /// keccak256 gives roots an EVM contract can check, written in 0x hex
+        #[arg(long, value_enum, default_value_t = HashFunction::Sha256)]
+        hasher: HashFunction,
+    },
+}
+
+struct Context {
+    rpc: RpcClient,
+    payer: Keypair,
+    program_id: Pubkey,
+    vault: Pubkey,
+    output: Output,
+}
+
+impl Context {
+    fn send(&self, instructions: &[Instruction]) -> Result<Signature, Box<dyn Error>> {
+        let mut tx = Transaction::new_with_payer(instructions, Some(&self.payer.pubkey()));
+        tx.sign(&[&self.payer], self.rpc.get_latest_blockhash()?);
+        Ok(self.rpc.send_and_confirm_transaction(&tx)?)
+    }
+
+    /// Serialized size of the transaction `send` builds for `instructions`.
+    fn transaction_size(&self, instructions: &[Instruction]) -> usize {
+        let message = Message::new(instructions, Some(&self.payer.pubkey()));
+        let signatures = usize::from(message.header.num_required_signatures);
+        // the signature count is a compact-u16, a single byte below 128
+        1 + signatures * 64 + message.serialize().len()
+    }
+
+    fn load_tree(&self) -> Result<(TreeAccount, Vec<Hash>, usize), Box<dyn Error>> {
+        let data = self.rpc.get_account_data(&self.vault)?;
+        let (tree_data, leaves) = TreeAccount::load(&data)?;
+        let capacity = (data.len() - TreeAccount::LEN) / std::mem::size_of::<Hash>();
+        Ok((*tree_data, leaves.to_vec(), capacity))
+    }
+
+    fn print(&self, text: String, value: serde_json::Value) {
+        match self.output {
+            Output::Text => println!("{}", text),
+            Output::Json => println!("{}", value),
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    // needs neither a keypair nor a cluster
+    if let Command::BuildProofs {
+        input,
+        format,
+        column,
+        out,
+        hasher,
+    } = &cli.command
+    {
+        let leaves = read_leaf_file(input, *format, column.as_deref())?
+            .into_iter()
+            .map(|leaf| Ok((leaf.clone(), decode_leaf(&leaf, cli.hex)?)))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        let proofs = match hasher {
+            HashFunction::Sha256 => build_proofs(&leaves),
+            HashFunction::Keccak256 => {
+                build_proofs_with_hasher::<Keccak256Hasher, _>(&leaves, HashEncoding::Hex)
+            }
+            HashFunction::Blake3 => {
+                build_proofs_with_hasher::<Blake3Hasher, _>(&leaves, HashEncoding::Base58)
+            }
+        };
+        fs::write(out, serde_json::to_string_pretty(&proofs)?)?;
+        let text = format!(
+            "root {}\n{} proofs written to {}",
+            proofs.root,
+            proofs.leaf_count,
+            out.display()
+        );
+        match cli.output {
+            Output::Text => println!("{}", text),
+            Output::Json => println!(
+                "{}",
+                json!({ "root": proofs.root, "leaf_count": proofs.leaf_count, "out": out })
+            ),
+        }
+        return Ok(());
+    }
+
+    let payer = read_keypair_file(expand_tilde(&cli.keypair))
+        .map_err(|err| format!("failed to read keypair {}: {}", cli.keypair, err))?;
+    let program_id = match cli.program_id {
+        Some(program_id) => program_id,
+        None => read_keypair_file("./target/deploy/merkle_tree_program-keypair.json")
+            .map_err(|err| format!("pass --program-id: {}", err))?
+            .pubkey(),
+    };
+    let vault = cli
+        .vault
+        .unwrap_or_else(|| client::vault_address(&program_id, &payer.pubkey(), cli.tree_id));
+    let ctx = Context {
+        rpc: RpcClient::new_with_commitment(cli.url, CommitmentConfig::confirmed()),
+        payer,
+        program_id,
+        vault,
+        output: cli.output,
+    };
+
+    match cli.command {
+        Command::Init { capacity } => {
+            let space = capacity.map_or(DEFAULT_ACCOUNT_SPACE, TreeAccount::space);
+            let ix = client::init_tree_with_space_ix(
+                &ctx.program_id,
+                &ctx.payer.pubkey(),
+                cli.tree_id,
+                space,
+            );
+            let signature = ctx.send(&[ix])?;
+            ctx.print(
+                format!("created vault {}\nsignature {}", ctx.vault, signature),
+                json!({ "vault": ctx.vault.to_string(), "signature": signature.to_string() }),
+            );
+        }
+        Command::AddLeaf { leaf } => {
+            let leaf = decode_leaf(&leaf, cli.hex)?;
+            let ix = client::add_leaf_ix(&ctx.program_id, &ctx.vault, &ctx.payer.pubkey(), &leaf);
+            let signature = ctx.send(&[ix])?;
+            let (tree_data, leaves, _) = ctx.load_tree()?;
+            ctx.print(
+                format!(
+                    "added leaf {} {}\nroot {}\nsignature {}",
+                    leaves.len() - 1,
+                    leaf_hash(&leaf),
+                    tree_data.root_hash,
+                    signature
+                ),
+                json!({
+                    "index": leaves.len() - 1,
+                    "leaf_hash": leaf_hash(&leaf).to_string(),
+                    "root": tree_data.root_hash.to_string(),
+                    "signature": signature.to_string(),
+                }),
+            );
+        }
+        Command::AddLeaves {
+            file,
+            format,
+            column,
+        } => {
+            let leaves = read_leaf_file(&file, format, column.as_deref())?
+                .iter()
+                .map(|leaf| decode_leaf(leaf, cli.hex))
+                .collect::<Result<Vec<_>, _>>()?;
+            let (_, stored, _) = ctx.load_tree()?;
+            let start = stored.len();
+            let add_leaves_ix = |batch: &[Vec<u8>]| {
+                client::add_leaves_ix(&ctx.program_id, &ctx.vault, &ctx.payer.pubkey(), batch)
+            };
+            let mut signatures = vec![];
+            for batch in batches(&leaves, |batch| {
+                ctx.transaction_size(&[add_leaves_ix(batch)])
+            })? {
+                match ctx.send(&[add_leaves_ix(batch)]) {
+                    Ok(signature) => signatures.push(signature.to_string()),
+                    Err(err) if is_account_full(err.as_ref()) => {
+                        let (_, stored, _) = ctx.load_tree()?;
+                        return Err(format!(
+                            "account is full after adding {} of {} leaves, run `resize` \
+                             with a larger capacity and add the remaining leaves",
+                            stored.len() - start,
+                            leaves.len()
+                        )
+                        .into());
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            // the last batch drops the leaves that don't fit
+            let (tree_data, stored, _) = ctx.load_tree()?;
+            let added = stored.len() - start;
+            let hint = if added < leaves.len() {
+                "\naccount is full, run `resize` to add the remaining leaves"
+            } else {
+                ""
+            };
+            ctx.print(
+                format!(
+                    "added {} of {} leaves in {} transactions\nleaf count {}\nroot {}{}",
+                    added,
+                    leaves.len(),
+                    signatures.len(),
+                    stored.len(),
+                    tree_data.root_hash,
+                    hint
+                ),
+                json!({
+                    "added": added,
+                    "leaf_count": stored.len(),
+                    "root": tree_data.root_hash.to_string(),
+                    "signatures": signatures,
+                }),
+            );
+        }
+        Command::Resize { capacity } => {
+            let size = ctx.rpc.get_account_data(&ctx.vault)?.len() as u64;
+            let new_size = TreeAccount::space(capacity);
+            // the data can only grow by `MAX_PERMITTED_DATA_INCREASE` per instruction
+            let mut signatures = vec![];
+            let mut step_size = size;
+            while step_size != new_size {
+                step_size = new_size.min(step_size + MAX_PERMITTED_DATA_INCREASE as u64);
+                let ix = client::resize_ix(
+                    &ctx.program_id,
+                    &ctx.vault,
+                    &ctx.payer.pubkey(),
+                    &ctx.payer.pubkey(),
+                    step_size,
+                );
+                signatures.push(ctx.send(&[ix])?.to_string());
+            }
+            ctx.print(
+                format!(
+                    "resized {} to {} bytes in {} transactions",
+                    ctx.vault,
+                    new_size,
+                    signatures.len()
+                ),
+                json!({
+                    "vault": ctx.vault.to_string(),
+                    "size": new_size,
+                    "signatures": signatures,
+                }),
+            );
+        }
+        Command::Show => {
+            let (tree_data, leaves, capacity) = ctx.load_tree()?;
+            ctx.print(
+                format!(
+                    "vault {}\ncreator {}\nauthority {}\ntree id {}\nleaves {} of {}\nroot {}",
+                    ctx.vault,
+                    tree_data.creator,
+                    tree_data.authority,
+                    tree_data
+                        .tree_id()
+                        .map_or("none".to_string(), |id| id.to_string()),
+                    leaves.len(),
+                    capacity,
+                    tree_data.root_hash
+                ),
+                json!({
+                    "vault": ctx.vault.to_string(),
+                    "creator": tree_data.creator.to_string(),
+                    "authority": tree_data.authority.to_string(),
+                    "tree_id": tree_data.tree_id(),
+                    "leaf_count": leaves.len(),
+                    "capacity": capacity,
+                    "root": tree_data.root_hash.to_string(),
+                }),
+            );
+        }
+        Command::Proof { index } => {
+            let (tree_data, leaves, _) = ctx.load_tree()?;
+            let tree = MerkleTree::from_leaf_hashes(&leaves);
+            // a proof from leaves that don't hash to the stored root would never verify
+            let root = tree.get_root().copied().unwrap_or(EMPTY_ROOT);
+            if root != tree_data.root_hash {
+                return Err(format!(
+                    "stored leaves hash to {}, not to the stored root {}",
+                    root, tree_data.root_hash
+                )
+                .into());
+            }
+            let proof = tree
+                .find_path(index as usize)
+                .ok_or_else(|| format!("no leaf at index {}", index))?
+                .siblings();
+            ctx.print(
+                format!(
+                    "leaf {} {}\nroot {}\nproof {}",
+                    index,
+                    leaves[index as usize],
+                    tree_data.root_hash,
+                    join(&proof)
+                ),
+                json!({
+                    "index": index,
+                    "leaf_hash": leaves[index as usize].to_string(),
+                    "root": tree_data.root_hash.to_string(),
+                    "proof": proof.iter().map(Hash::to_string).collect::<Vec<_>>(),
+                }),
+            );
+        }
+        Command::Verify { index, leaf, proof } => {
+            let leaf = decode_leaf(&leaf, cli.hex)?;
+            let proof = match proof {
+                Some(proof) => proof,
+                None => {
+                    let (_, leaves, _) = ctx.load_tree()?;
+                    MerkleTree::from_leaf_hashes(&leaves)
+                        .find_path(index as usize)
+                        .map(|proof| proof.siblings())
+                        .unwrap_or_default()
+                }
+            };
+            let is_valid = simulate_verify(&ctx, &leaf, index, proof)?;
+            ctx.print(
+                format!(
+                    "leaf {} is {}",
+                    index,
+                    if is_valid { "valid" } else { "invalid" }
+                ),
+                json!({ "index": index, "valid": is_valid }),
+            );
+        }
+        Command::Export { file } => {
+            let (tree_data, leaves, _) = ctx.load_tree()?;
+            let content = match ctx.output {
+                Output::Text => std::iter::once(format!("root {}", tree_data.root_hash))
+                    .chain(leaves.iter().map(Hash::to_string))
+                    .map(|line| line + "\n")
+                    .collect(),
+                Output::Json => json!({
+                    "root": tree_data.root_hash.to_string(),
+                    "leaves": leaves.iter().map(Hash::to_string).collect::<Vec<_>>(),
+                })
+                .to_string(),
+            };
+            match file {
+                Some(file) => fs::write(file, content)?,
+                None => print!("{}", content),
+            }
+        }
+        Command::BuildProofs { .. } => unreachable!("handled before connecting"),
+    }
+    Ok(())
+}
+
+/// Runs `VerifyLeaf` without sending it, the result comes back as return data.
+fn simulate_verify(
+    ctx: &Context,
+    leaf: &[u8],
+    index: u64,
+    proof: Vec<Hash>,
+) -> Result<bool, Box<dyn Error>> {
+    let ix = client::verify_ix(&ctx.program_id, &ctx.vault, leaf, index, proof);
+    let mut tx = Transaction::new_with_payer(&[ix], Some(&ctx.payer.pubkey()));
+    tx.sign(&[&ctx.payer], ctx.rpc.get_latest_blockhash()?);
+    let result = ctx.rpc.simulate_transaction(&tx)?.value;
+    if let Some(err) = result.err {
+        return Err(format!("verify failed: {}", err).into());
+    }
+    let return_data = result.return_data.ok_or("no return data")?;
+    Ok(STANDARD.decode(return_data.data.0)? == [1])
+}
+
+/// Whether the transaction failed because the account has no room left.
+fn is_account_full(err: &(dyn Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<ClientError>()
+            .and_then(ClientError::get_transaction_error),
+        Some(TransactionError::InstructionError(_, InstructionError::Custom(code)))
+            if code == CustomError::AccountFull as u32
+    )
+}
+
+/// Splits `leaves` into batches of at most `MAX_BATCH_LEAVES` whose transaction,
+/// of `transaction_size` bytes, fits in a packet. Fails on a leaf that doesn't fit
+/// in a transaction of its own.
+fn batches(
+    leaves: &[Vec<u8>],
+    transaction_size: impl Fn(&[Vec<u8>]) -> usize,
+) -> Result<Vec<&[Vec<u8>]>, Box<dyn Error>> {
+    let mut batches = vec![];
+    let mut start = 0;
+    while start < leaves.len() {
+        let mut end = start;
+        while end < leaves.len()
+            && end - start < MAX_BATCH_LEAVES
+            && transaction_size(&leaves[start..=end]) <= PACKET_DATA_SIZE
+        {
+            end += 1;
+        }
+        if end == start {
+            return Err(format!("leaf {} is too large for a transaction", start).into());
+        }
+        batches.push(&leaves[start..end]);
+        start = end;
+    }
+    Ok(batches)
+}
+
+/// Bytes of `leaf`, hex decoded with `--hex`.
+fn decode_leaf(leaf: &str, hex: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(if hex {
+        hex::decode(leaf)?
+    } else {
+        leaf.as_bytes().to_vec()
+    })
+}
+
+/// Reads the leaves of `path` with `proofs::read_leaves`, guessing the format from
+/// the extension if not given.
+fn read_leaf_file(
+    path: &Path,
+    format: Option<LeafFormat>,
+    column: Option<&str>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let format = format.unwrap_or_else(|| LeafFormat::from_path(path));
+    Ok(read_leaves(&fs::read_to_string(path)?, format, column)?)
+}
+
+fn expand_tilde(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
+
+fn join(hashes: &[Hash]) -> String {
+    hashes
+        .iter()
+        .map(Hash::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a transaction of 100 bytes plus the leaves
+    fn size(batch: &[Vec<u8>]) -> usize {
+        100 + batch.iter().map(Vec::len).sum::<usize>()
+    }
+
+    #[test]
+    fn test_batches_byte_limit() {
+        let leaves = vec![vec![0u8; 350]; 7];
+        let batches = batches(&leaves, size).unwrap();
+        let lens: Vec<_> = batches.iter().map(|batch| batch.len()).collect();
+        assert_eq!(lens, [3, 3, 1]);
+        assert!(batches.iter().all(|batch| size(batch) <= PACKET_DATA_SIZE));
+    }
+
+    #[test]
+    fn test_batches_leaf_cap() {
+        let leaves = vec![vec![0u8]; MAX_BATCH_LEAVES * 2 + 1];
+        let lens: Vec<_> = batches(&leaves, size)
+            .unwrap()
+            .iter()
+            .map(|batch| batch.len())
+            .collect();
+        assert_eq!(lens, [MAX_BATCH_LEAVES, MAX_BATCH_LEAVES, 1]);
+    }
+
+    #[test]
+    fn test_batches_leaf_too_large() {
+        let leaves = vec![vec![0u8; 10], vec![0u8; PACKET_DATA_SIZE]];
+        let err = batches(&leaves, size).unwrap_err();
+        assert_eq!(err.to_string(), "leaf 1 is too large for a transaction");
+    }
+}
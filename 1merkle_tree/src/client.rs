@@ -0,0 +1,211 @@
+use solana_program::{
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+
+use crate::{instructions::CounterInstruction, state::DEFAULT_ACCOUNT_SPACE};
+
+pub use crate::state::{find_counter_address, find_vault_address};
+
+/// Address of the vault of the tree `tree_id` created by `payer`.
+pub fn vault_address(program_id: &Pubkey, payer: &Pubkey, tree_id: u64) -> Pubkey {
+    find_vault_address(program_id, payer, tree_id).0
+}
+
+/// Address of the counter `counter_id` created by `payer`.
+pub fn counter_address(program_id: &Pubkey, payer: &Pubkey, counter_id: u64) -> Pubkey {
+    find_counter_address(program_id, payer, counter_id).0
+}
+
+/// Creates the tree `tree_id` of `payer` with `DEFAULT_ACCOUNT_SPACE` bytes.
+pub fn init_tree_ix(program_id: &Pubkey, payer: &Pubkey, tree_id: u64) -> Instruction {
+    init_tree_with_space_ix(program_id, payer, tree_id, DEFAULT_ACCOUNT_SPACE)
+}
+
+/// Creates the tree `tree_id` of `payer` with `space` bytes, see
+/// `state::TreeAccount::space`.
+pub fn init_tree_with_space_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    tree_id: u64,
+    space: u64,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::InitTree { tree_id, space },
+        vec![
+            AccountMeta::new(vault_address(program_id, payer, tree_id), false),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn init_counter_ix(program_id: &Pubkey, payer: &Pubkey, counter_id: u64) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::InitCounter { counter_id },
+        vec![
+            AccountMeta::new(counter_address(program_id, payer, counter_id), false),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Increments the counter if `increment`, decrements it otherwise.
+pub fn change_counter_ix(
+    program_id: &Pubkey,
+    counter: &Pubkey,
+    authority: &Pubkey,
+    increment: bool,
+) -> Instruction {
+    let data = if increment {
+        CounterInstruction::IncCounter
+    } else {
+        CounterInstruction::DecCounter
+    };
+    Instruction::new_with_borsh(*program_id, &data, authority_accounts(counter, authority))
+}
+
+/// Appends `leaf` with `AddLeafBytes`, which hashes text the same way as `AddLeaf`.
+pub fn add_leaf_ix<T: AsRef<[u8]>>(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    authority: &Pubkey,
+    leaf: T,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::AddLeafBytes(leaf.as_ref().to_vec()),
+        authority_accounts(vault, authority),
+    )
+}
+
+pub fn add_leaves_ix<T: AsRef<[u8]>>(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    authority: &Pubkey,
+    leaves: &[T],
+) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::AddLeaves(leaves.iter().map(|leaf| leaf.as_ref().to_vec()).collect()),
+        authority_accounts(vault, authority),
+    )
+}
+
+/// Checks `leaf` at `index` against the root, the result is set as return data.
+pub fn verify_ix<T: AsRef<[u8]>>(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    leaf: T,
+    index: u64,
+    proof: Vec<Hash>,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::VerifyLeaf {
+            leaf: leaf.as_ref().to_vec(),
+            index,
+            proof,
+        },
+        vec![AccountMeta::new_readonly(*vault, false)],
+    )
+}
+
+pub fn update_leaf_ix<T: AsRef<[u8]>>(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    authority: &Pubkey,
+    index: u64,
+    new_leaf: T,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::UpdateLeaf {
+            index,
+            new_leaf: new_leaf.as_ref().to_vec(),
+        },
+        authority_accounts(vault, authority),
+    )
+}
+
+pub fn remove_leaf_ix(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    authority: &Pubkey,
+    index: u64,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::RemoveLeaf { index },
+        authority_accounts(vault, authority),
+    )
+}
+
+/// Works for trees and counters alike.
+pub fn set_authority_ix(
+    program_id: &Pubkey,
+    account: &Pubkey,
+    authority: &Pubkey,
+    new_authority: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::SetAuthority {
+            new_authority: *new_authority,
+        },
+        authority_accounts(account, authority),
+    )
+}
+
+pub fn resize_ix(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    new_size: u64,
+) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::Resize { new_size },
+        vec![
+            AccountMeta::new(*vault, false),
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Upgrades `account` to the current layout, `authority` pays for any extra space.
+pub fn migrate_ix(program_id: &Pubkey, account: &Pubkey, authority: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::Migrate,
+        vec![
+            AccountMeta::new(*account, false),
+            AccountMeta::new(*authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Rewrites a vault that still stores its leaves as strings.
+pub fn migrate_leafs_ix(program_id: &Pubkey, vault: &Pubkey, creator: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(
+        *program_id,
+        &CounterInstruction::MigrateLeafs,
+        authority_accounts(vault, creator),
+    )
+}
+
+fn authority_accounts(account: &Pubkey, authority: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*account, false),
+        AccountMeta::new_readonly(*authority, true),
+    ]
+}
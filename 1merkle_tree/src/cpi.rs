@@ -0,0 +1,65 @@
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    hash::Hash,
+    program::{get_return_data, invoke, invoke_signed},
+    program_error::ProgramError,
+};
+
+use crate::client;
+
+/// Appends `leaf` to the tree in `vault`. `signer_seeds` sign for `authority` when
+/// it is a PDA of the calling program, pass `&[]` otherwise.
+pub fn add_leaf<'a>(
+    merkle_program: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    leaf: &[u8],
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let ix = client::add_leaf_ix(merkle_program.key, vault.key, authority.key, leaf);
+    invoke_signed(&ix, &[vault.clone(), authority.clone()], signer_seeds)
+}
+
+/// Appends `leaves` to the tree in `vault` and returns how many fit, see
+/// `CounterInstruction::AddLeaves`.
+pub fn add_leaves<'a, T: AsRef<[u8]>>(
+    merkle_program: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    leaves: &[T],
+    signer_seeds: &[&[&[u8]]],
+) -> Result<u64, ProgramError> {
+    let ix = client::add_leaves_ix(merkle_program.key, vault.key, authority.key, leaves);
+    invoke_signed(&ix, &[vault.clone(), authority.clone()], signer_seeds)?;
+    let data = return_data(merkle_program)?;
+    let accepted = data
+        .try_into()
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    Ok(u64::from_le_bytes(accepted))
+}
+
+/// Checks `leaf` at `index` against the root stored in `vault`.
+pub fn verify_leaf<'a, T: AsRef<[u8]>>(
+    merkle_program: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    leaf: T,
+    index: u64,
+    proof: Vec<Hash>,
+) -> Result<bool, ProgramError> {
+    let ix = client::verify_ix(merkle_program.key, vault.key, leaf, index, proof);
+    invoke(&ix, std::slice::from_ref(vault))?;
+    match return_data(merkle_program)?.as_slice() {
+        [is_valid] => Ok(*is_valid != 0),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+/// Return data set by the tree program, so data left by another program isn't read
+/// by mistake.
+fn return_data(merkle_program: &AccountInfo) -> Result<Vec<u8>, ProgramError> {
+    match get_return_data() {
+        Some((program_id, data)) if program_id == *merkle_program.key => Ok(data),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
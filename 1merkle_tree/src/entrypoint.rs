@@ -1,29 +1,60 @@
 use instructions::{CounterInstruction, Unpack};
 use processor::*;
+use solana_program::msg;
 use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
-use solana_program::{entrypoint, msg};
 
+#[cfg(any(test, feature = "client"))]
+pub mod client;
+#[cfg(any(test, feature = "cpi"))]
+pub mod cpi;
 pub mod error;
+pub mod events;
+#[cfg(any(test, feature = "indexer"))]
+pub mod indexer;
 pub mod instructions;
 pub mod merkle_tree;
 pub mod processor;
+#[cfg(any(test, feature = "cli"))]
+pub mod proofs;
+pub mod sparse_merkle_tree;
 pub mod state;
 
-entrypoint!(entrypoints);
+// Programs linking this crate to call it through CPI enable `no-entrypoint`, so
+// they don't end up with two entrypoints.
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(entrypoints);
 pub fn entrypoints(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     instruction_data: &[u8],
 ) -> ProgramResult {
-    msg!("pub fn entrypoints {}", instruction_data[0]);
     let instruction = CounterInstruction::unpack(instruction_data)?;
+    msg!("pub fn entrypoints {}", instruction_data[0]);
     match instruction {
-        CounterInstruction::InitCounter(init_val) => {
-            process_initialize_counter(program_id, accounts, init_val)
+        CounterInstruction::InitTree { tree_id, space } => {
+            process_initialize_tree(program_id, accounts, tree_id, space)
         }
         CounterInstruction::IncCounter => process_change_counter(program_id, accounts, true),
         CounterInstruction::DecCounter => process_change_counter(program_id, accounts, false),
-        CounterInstruction::AddLeaf(leaf) => add_leaf(program_id, accounts, leaf),
+        CounterInstruction::AddLeaf(leaf) => add_leaf(program_id, accounts, leaf.as_bytes()),
+        CounterInstruction::MigrateLeafs => migrate_leafs(program_id, accounts),
+        CounterInstruction::VerifyLeaf { leaf, index, proof } => {
+            verify_leaf(program_id, accounts, leaf, index, proof)
+        }
+        CounterInstruction::UpdateLeaf { index, new_leaf } => {
+            update_leaf(program_id, accounts, index, new_leaf)
+        }
+        CounterInstruction::RemoveLeaf { index } => remove_leaf(program_id, accounts, index),
+        CounterInstruction::SetAuthority { new_authority } => {
+            set_authority(program_id, accounts, new_authority)
+        }
+        CounterInstruction::Resize { new_size } => resize(program_id, accounts, new_size),
+        CounterInstruction::InitCounter { counter_id } => {
+            process_initialize_counter(program_id, accounts, counter_id)
+        }
+        CounterInstruction::Migrate => migrate(program_id, accounts),
+        CounterInstruction::AddLeaves(leaves) => add_leaves(program_id, accounts, leaves),
+        CounterInstruction::AddLeafBytes(leaf) => add_leaf(program_id, accounts, &leaf),
     }
 }
 
@@ -1,12 +1,38 @@
 use solana_program::program_error::ProgramError;
 use thiserror::Error;
 
+// The discriminants are the `ProgramError::Custom` codes clients see, so existing
+// variants must keep their values.
 #[derive(Debug, Error)]
 pub enum CustomError {
     #[error("Operation will overflow")]
-    Overflow,
+    Overflow = 0,
     #[error("Operation will underflow")]
-    Underflow,
+    Underflow = 1,
+    #[error("Signer is not allowed to modify this account")]
+    Unauthorized = 2,
+    #[error("Leaf index is out of bounds")]
+    InvalidLeafIndex = 3,
+    #[error("Account is required to sign")]
+    NotSigner = 4,
+    #[error("Account is required to be writable")]
+    NotWritable = 5,
+    #[error("Account address does not match the expected PDA")]
+    InvalidPda = 6,
+    #[error("Account is already initialized")]
+    AlreadyInitialized = 7,
+    #[error("Account data could not be deserialized")]
+    AccountDataCorrupt = 8,
+    #[error("Tree already holds the most leaves it can")]
+    TreeFull = 9,
+    #[error("Account is not the system program")]
+    InvalidSystemProgram = 10,
+    #[error("Account data is too small for the state, resize the account")]
+    AccountFull = 11,
+    #[error("Account holds a different type of state")]
+    InvalidAccountType = 12,
+    #[error("Account layout version is not supported, run Migrate")]
+    UnsupportedVersion = 13,
 }
 
 impl From<CustomError> for ProgramError {
@@ -0,0 +1,196 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{hash::Hash, log::sol_log_data, pubkey::Pubkey};
+
+/// Written in front of every Borsh encoded event, so other data logged by the
+/// program is not mistaken for one.
+pub const EVENT_DISCRIMINATOR: [u8; 8] = *b"mrklevnt";
+
+const DATA_PREFIX: &str = "Program data: ";
+
+/// Changes to a tree, logged with `sol_log_data` so indexers can follow the tree
+/// without fetching the account. The account only stores leaf hashes, the events
+/// carry the raw leaves as well. `tree` is the vault the event belongs to, since one
+/// transaction can change several trees.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum MerkleEvent {
+    /// Leaf number `index` was appended by `AddLeaf` or `AddLeafBytes`.
+    LeafAdded {
+        tree: Pubkey,
+        index: u64,
+        leaf: Vec<u8>,
+        leaf_hash: Hash,
+        new_root: Hash,
+        leaf_count: u64,
+    },
+    /// `leaf_hashes` were appended by `AddLeaves`, starting at `start_index`. The root
+    /// is only computed after the last one.
+    LeavesAdded {
+        tree: Pubkey,
+        start_index: u64,
+        leaves: Vec<Vec<u8>>,
+        leaf_hashes: Vec<Hash>,
+        new_root: Hash,
+        leaf_count: u64,
+    },
+    /// The leaf at `index` was replaced by `UpdateLeaf`.
+    LeafUpdated {
+        tree: Pubkey,
+        index: u64,
+        leaf: Vec<u8>,
+        leaf_hash: Hash,
+        new_root: Hash,
+    },
+    /// The leaf at `index` was replaced by `state::REMOVED_LEAF`.
+    LeafRemoved {
+        tree: Pubkey,
+        index: u64,
+        new_root: Hash,
+    },
+}
+
+impl MerkleEvent {
+    /// Vault of the tree that changed.
+    pub fn tree(&self) -> &Pubkey {
+        match self {
+            MerkleEvent::LeafAdded { tree, .. }
+            | MerkleEvent::LeavesAdded { tree, .. }
+            | MerkleEvent::LeafUpdated { tree, .. }
+            | MerkleEvent::LeafRemoved { tree, .. } => tree,
+        }
+    }
+
+    /// Root of the tree after the change.
+    pub fn new_root(&self) -> &Hash {
+        match self {
+            MerkleEvent::LeafAdded { new_root, .. }
+            | MerkleEvent::LeavesAdded { new_root, .. }
+            | MerkleEvent::LeafUpdated { new_root, .. }
+            | MerkleEvent::LeafRemoved { new_root, .. } => new_root,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = EVENT_DISCRIMINATOR.to_vec();
+        self.serialize(&mut data).unwrap();
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let event = data.strip_prefix(&EVENT_DISCRIMINATOR)?;
+        Self::try_from_slice(event).ok()
+    }
+
+    pub fn emit(&self) {
+        sol_log_data(&[&self.to_bytes()]);
+    }
+
+    /// Parses a single `Program data:` log line.
+    pub fn from_log(line: &str) -> Option<Self> {
+        let data = STANDARD.decode(line.strip_prefix(DATA_PREFIX)?).ok()?;
+        Self::from_bytes(&data)
+    }
+}
+
+/// Parses the events logged by `program_id` out of the logs of a transaction, e.g.
+/// `RpcLogsResponse::logs`. Lines are attributed to the program on top of the
+/// invoke stack, so another program can't forge events by logging the same data.
+/// The logs of a failed transaction still hold the events emitted before the
+/// failure, the caller should skip those. Events of every tree are returned, see
+/// `MerkleEvent::tree`.
+pub fn parse_events<S: AsRef<str>>(program_id: &Pubkey, logs: &[S]) -> Vec<MerkleEvent> {
+    let program_id = program_id.to_string();
+    let mut stack: Vec<&str> = vec![];
+    let mut events = vec![];
+    for line in logs {
+        let line = line.as_ref();
+        if line.starts_with(DATA_PREFIX) {
+            if stack.last() == Some(&program_id.as_str()) {
+                events.extend(MerkleEvent::from_log(line));
+            }
+            continue;
+        }
+        let mut words = line.split(' ');
+        match (words.next(), words.next(), words.next()) {
+            (Some("Program"), Some(id), Some("invoke")) => stack.push(id),
+            (Some("Program"), Some(_), Some("success")) => {
+                stack.pop();
+            }
+            (Some("Program"), Some(_), Some("failed:")) => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_line(event: &MerkleEvent) -> String {
+        format!("{}{}", DATA_PREFIX, STANDARD.encode(event.to_bytes()))
+    }
+
+    #[test]
+    fn test_event_roundtrip() {
+        let event = MerkleEvent::LeafAdded {
+            tree: Pubkey::new_unique(),
+            index: 3,
+            leaf: b"Test-string1".to_vec(),
+            leaf_hash: Hash::new_unique(),
+            new_root: Hash::new_unique(),
+            leaf_count: 4,
+        };
+        assert_eq!(MerkleEvent::from_log(&data_line(&event)), Some(event));
+        assert_eq!(MerkleEvent::from_log("Program log: add_leaf"), None);
+        assert_eq!(
+            MerkleEvent::from_log(&format!("{}{}", DATA_PREFIX, STANDARD.encode(b"other"))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let program_id = Pubkey::new_unique();
+        let other_id = Pubkey::new_unique();
+        let tree = Pubkey::new_unique();
+        let added = MerkleEvent::LeafAdded {
+            tree,
+            index: 0,
+            leaf: b"Test-string1".to_vec(),
+            leaf_hash: Hash::new_unique(),
+            new_root: Hash::new_unique(),
+            leaf_count: 1,
+        };
+        let removed = MerkleEvent::LeafRemoved {
+            tree,
+            index: 0,
+            new_root: Hash::new_unique(),
+        };
+        let forged = MerkleEvent::LeafRemoved {
+            tree,
+            index: 1,
+            new_root: Hash::new_unique(),
+        };
+        let logs = vec![
+            format!("Program {} invoke [1]", program_id),
+            "Program log: add_leaf: 12 bytes".to_string(),
+            data_line(&added),
+            format!(
+                "Program {} consumed 5000 of 200000 compute units",
+                program_id
+            ),
+            format!("Program {} success", program_id),
+            format!("Program {} invoke [1]", other_id),
+            data_line(&forged),
+            format!("Program {} invoke [2]", program_id),
+            data_line(&removed),
+            format!("Program {} success", program_id),
+            data_line(&forged),
+            format!("Program {} success", other_id),
+        ];
+        assert_eq!(parse_events(&program_id, &logs), vec![added, removed]);
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use solana_program::{hash::Hash, program_error::ProgramError, pubkey::Pubkey};
+use thiserror::Error;
+
+use crate::{
+    events::{parse_events, MerkleEvent},
+    merkle_tree::{MerkleTree, EMPTY_ROOT},
+    state::{TreeAccount, REMOVED_LEAF},
+};
+
+#[derive(Debug, Error)]
+pub enum IndexerError {
+    #[error(
+        "Event for leaf {index} but the indexer holds {leaf_count} leaves, events were missed"
+    )]
+    MissedEvents { index: u64, leaf_count: u64 },
+    #[error("Leaf {index} differs from the indexed one")]
+    LeafMismatch { index: u64 },
+    #[error("Rebuilt root {actual} does not match the reported root {expected}")]
+    RootMismatch { expected: Hash, actual: Hash },
+    #[error("Account is not a tree: {0}")]
+    InvalidAccount(ProgramError),
+    #[error("Leaves file is corrupt: {0}")]
+    CorruptFile(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Follows a single tree through the events its program logs, and keeps a full copy
+/// of it to serve proofs from. Events of other trees are ignored.
+pub struct Indexer {
+    program_id: Pubkey,
+    /// Vault of the followed tree.
+    tree_address: Pubkey,
+    leaves: Vec<Hash>,
+    tree: MerkleTree,
+    path: Option<PathBuf>,
+}
+
+impl Indexer {
+    /// Starts from an empty tree, e.g. right after `InitTree`.
+    pub fn new(program_id: Pubkey, tree_address: Pubkey) -> Self {
+        Self::from_leaves(program_id, tree_address, vec![])
+    }
+
+    pub fn from_leaves(program_id: Pubkey, tree_address: Pubkey, leaves: Vec<Hash>) -> Self {
+        let tree = MerkleTree::from_leaf_hashes(&leaves);
+        Self {
+            program_id,
+            tree_address,
+            leaves,
+            tree,
+            path: None,
+        }
+    }
+
+    /// Starts from the current state of the vault, for a tree that already has leaves.
+    pub fn from_account_data(program_id: Pubkey, data: &[u8]) -> Result<Self, IndexerError> {
+        let (tree_data, leaves) = TreeAccount::load(data).map_err(IndexerError::InvalidAccount)?;
+        let tree_address = tree_data.find_vault_address(&program_id).0;
+        let indexer = Self::from_leaves(program_id, tree_address, leaves.to_vec());
+        check_root(&indexer.tree, &tree_data.root_hash)?;
+        Ok(indexer)
+    }
+
+    /// Loads the leaves saved at `path`, or starts empty if there is no such file.
+    /// `save` writes back to the same file.
+    pub fn open<P: AsRef<Path>>(
+        program_id: Pubkey,
+        tree_address: Pubkey,
+        path: P,
+    ) -> Result<Self, IndexerError> {
+        let leaves = match fs::read_to_string(&path) {
+            Ok(content) => content
+                .lines()
+                .map(|line| {
+                    Hash::from_str(line).map_err(|_| IndexerError::CorruptFile(line.to_string()))
+                })
+                .collect::<Result<_, _>>()?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self::from_leaves(program_id, tree_address, leaves).with_path(path))
+    }
+
+    /// Sets the file `save` writes to, without loading it.
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Writes the leaves to the file given to `open`, one base58 hash per line. The
+    /// file is replaced at once, so a crash leaves the previous version.
+    pub fn save(&self) -> Result<(), IndexerError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let content: String = self
+            .leaves
+            .iter()
+            .map(|leaf| format!("{}\n", leaf))
+            .collect();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Applies the events of the followed tree found in the logs of one transaction,
+    /// see `events::parse_events`. Returns how many were applied.
+    pub fn process_logs<S: AsRef<str>>(&mut self, logs: &[S]) -> Result<usize, IndexerError> {
+        let events: Vec<_> = parse_events(&self.program_id, logs)
+            .into_iter()
+            .filter(|event| event.tree() == &self.tree_address)
+            .collect();
+        for event in &events {
+            self.apply(event)?;
+        }
+        Ok(events.len())
+    }
+
+    /// Applies `event` and checks the rebuilt root against the one it reports. Appends
+    /// of leaves already indexed with the same hash are skipped, so a transaction
+    /// delivered twice is harmless. Older logs can't be replayed, a leaf updated or
+    /// removed since fails with `LeafMismatch`: start over from `from_account_data`
+    /// instead. Events of other trees are ignored. The indexer is left unchanged if
+    /// the event fails.
+    pub fn apply(&mut self, event: &MerkleEvent) -> Result<(), IndexerError> {
+        if event.tree() != &self.tree_address {
+            return Ok(());
+        }
+        let mut leaves = self.leaves.clone();
+        let changed = match event {
+            MerkleEvent::LeafAdded {
+                index, leaf_hash, ..
+            } => append(&mut leaves, *index, &[*leaf_hash])?,
+            MerkleEvent::LeavesAdded {
+                start_index,
+                leaf_hashes,
+                ..
+            } => append(&mut leaves, *start_index, leaf_hashes)?,
+            MerkleEvent::LeafUpdated {
+                index, leaf_hash, ..
+            } => replace(&mut leaves, *index, *leaf_hash)?,
+            MerkleEvent::LeafRemoved { index, .. } => replace(&mut leaves, *index, REMOVED_LEAF)?,
+        };
+        if !changed {
+            return Ok(());
+        }
+        let tree = MerkleTree::from_leaf_hashes(&leaves);
+        check_root(&tree, event.new_root())?;
+        self.leaves = leaves;
+        self.tree = tree;
+        Ok(())
+    }
+
+    pub fn tree_address(&self) -> &Pubkey {
+        &self.tree_address
+    }
+
+    pub fn leaves(&self) -> &[Hash] {
+        &self.leaves
+    }
+
+    pub fn root(&self) -> Hash {
+        root(&self.tree)
+    }
+
+    /// Sibling hashes of the leaf at `index`, as `CounterInstruction::VerifyLeaf`
+    /// expects them.
+    pub fn proof(&self, index: usize) -> Option<Vec<Hash>> {
+        Some(self.tree.find_path(index)?.siblings())
+    }
+}
+
+fn root(tree: &MerkleTree) -> Hash {
+    tree.get_root().copied().unwrap_or(EMPTY_ROOT)
+}
+
+fn check_root(tree: &MerkleTree, expected: &Hash) -> Result<(), IndexerError> {
+    let actual = root(tree);
+    if actual != *expected {
+        return Err(IndexerError::RootMismatch {
+            expected: *expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Returns whether any of the leaves was new.
+fn append(
+    leaves: &mut Vec<Hash>,
+    start_index: u64,
+    leaf_hashes: &[Hash],
+) -> Result<bool, IndexerError> {
+    let leaf_count = leaves.len() as u64;
+    if start_index > leaf_count {
+        return Err(IndexerError::MissedEvents {
+            index: start_index,
+            leaf_count,
+        });
+    }
+    for (index, leaf_hash) in (start_index..).zip(leaf_hashes) {
+        match leaves.get(index as usize) {
+            None => leaves.push(*leaf_hash),
+            Some(indexed) if indexed == leaf_hash => {}
+            Some(_) => return Err(IndexerError::LeafMismatch { index }),
+        }
+    }
+    Ok(leaves.len() as u64 > leaf_count)
+}
+
+fn replace(leaves: &mut [Hash], index: u64, leaf_hash: Hash) -> Result<bool, IndexerError> {
+    let leaf_count = leaves.len() as u64;
+    let leaf = leaves
+        .get_mut(index as usize)
+        .ok_or(IndexerError::MissedEvents { index, leaf_count })?;
+    *leaf = leaf_hash;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle_tree::leaf_hash;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    const TREE: Pubkey = Pubkey::new_from_array([7; 32]);
+
+    fn added(leaves: &[&str], index: usize) -> MerkleEvent {
+        MerkleEvent::LeafAdded {
+            tree: TREE,
+            index: index as u64,
+            leaf: leaves[index].as_bytes().to_vec(),
+            leaf_hash: leaf_hash(leaves[index]),
+            new_root: *MerkleTree::new(&leaves[..=index]).get_root().unwrap(),
+            leaf_count: index as u64 + 1,
+        }
+    }
+
+    #[test]
+    fn test_apply_events() {
+        let leaves = ["Test-string1", "Test-string2", "Test-string3"];
+        let mut indexer = Indexer::new(Pubkey::new_unique(), TREE);
+        assert_eq!(indexer.root(), EMPTY_ROOT);
+
+        for index in 0..leaves.len() {
+            indexer.apply(&added(&leaves, index)).unwrap();
+        }
+        // an append delivered twice is skipped
+        indexer.apply(&added(&leaves, 1)).unwrap();
+        assert_eq!(indexer.leaves().len(), 3);
+
+        let tree = MerkleTree::new(&leaves);
+        assert_eq!(indexer.root(), *tree.get_root().unwrap());
+        assert_eq!(
+            indexer.proof(1),
+            Some(tree.find_path(1).unwrap().siblings())
+        );
+        assert_eq!(indexer.proof(3), None);
+
+        let mut removed = leaves.map(leaf_hash);
+        removed[0] = REMOVED_LEAF;
+        indexer
+            .apply(&MerkleEvent::LeafRemoved {
+                tree: TREE,
+                index: 0,
+                new_root: *MerkleTree::from_leaf_hashes(&removed).get_root().unwrap(),
+            })
+            .unwrap();
+        assert_eq!(indexer.leaves()[0], REMOVED_LEAF);
+        // the removal superseded the append
+        assert!(matches!(
+            indexer.apply(&added(&leaves, 0)),
+            Err(IndexerError::LeafMismatch { index: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_process_logs() {
+        let program_id = Pubkey::new_unique();
+        let leaves = ["Test-string1", "Test-string2", "Test-string3"];
+        let added = MerkleEvent::LeavesAdded {
+            tree: TREE,
+            start_index: 0,
+            leaves: leaves.iter().map(|leaf| leaf.as_bytes().to_vec()).collect(),
+            leaf_hashes: leaves.map(leaf_hash).to_vec(),
+            new_root: *MerkleTree::new(&leaves).get_root().unwrap(),
+            leaf_count: 3,
+        };
+        // what the runtime logs for `MerkleEvent::emit`
+        let logs = [
+            format!("Program {} invoke [1]", program_id),
+            "Program log: add_leaves: 3".to_string(),
+            format!("Program data: {}", STANDARD.encode(added.to_bytes())),
+            format!("Program {} success", program_id),
+        ];
+
+        let mut indexer = Indexer::new(program_id, TREE);
+        assert_eq!(indexer.process_logs(&logs).unwrap(), 1);
+        assert_eq!(indexer.leaves(), &leaves.map(leaf_hash));
+        assert_eq!(indexer.root(), *added.new_root());
+
+        // events of another tree are ignored
+        let mut other = Indexer::new(program_id, Pubkey::new_unique());
+        assert_eq!(other.process_logs(&logs).unwrap(), 0);
+        other.apply(&added).unwrap();
+        assert!(other.leaves().is_empty());
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let leaves = ["Test-string1", "Test-string2"];
+        let mut indexer = Indexer::new(Pubkey::new_unique(), TREE);
+        assert!(matches!(
+            indexer.apply(&added(&leaves, 1)),
+            Err(IndexerError::MissedEvents {
+                index: 1,
+                leaf_count: 0
+            })
+        ));
+
+        let mut event = added(&leaves, 0);
+        if let MerkleEvent::LeafAdded { new_root, .. } = &mut event {
+            *new_root = Hash::default();
+        }
+        assert!(matches!(
+            indexer.apply(&event),
+            Err(IndexerError::RootMismatch { .. })
+        ));
+        // the failed event left no trace, the right one still applies
+        assert!(indexer.leaves().is_empty());
+        assert_eq!(indexer.root(), EMPTY_ROOT);
+        indexer.apply(&added(&leaves, 0)).unwrap();
+        indexer.apply(&added(&leaves, 1)).unwrap();
+
+        let mut event = MerkleEvent::LeafRemoved {
+            tree: TREE,
+            index: 0,
+            new_root: Hash::default(),
+        };
+        assert!(matches!(
+            indexer.apply(&event),
+            Err(IndexerError::RootMismatch { .. })
+        ));
+        assert_eq!(indexer.leaves(), &leaves.map(leaf_hash));
+        if let MerkleEvent::LeafRemoved { index, .. } = &mut event {
+            *index = 2;
+        }
+        assert!(matches!(
+            indexer.apply(&event),
+            Err(IndexerError::MissedEvents { index: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let program_id = Pubkey::new_unique();
+        let path = std::env::temp_dir().join(format!("indexer-{}.leaves", program_id));
+        let leaves = ["Test-string1", "Test-string2"];
+
+        let mut indexer = Indexer::open(program_id, TREE, &path).unwrap();
+        assert!(indexer.leaves().is_empty());
+        indexer.apply(&added(&leaves, 0)).unwrap();
+        indexer.apply(&added(&leaves, 1)).unwrap();
+        indexer.save().unwrap();
+
+        let reopened = Indexer::open(program_id, TREE, &path).unwrap();
+        assert_eq!(reopened.leaves(), indexer.leaves());
+        assert_eq!(reopened.root(), indexer.root());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::program_error::ProgramError;
+use solana_program::{hash::Hash, program_error::ProgramError, pubkey::Pubkey};
 
 pub trait Unpack {
     fn unpack(input: &[u8]) -> Result<Self, ProgramError>
@@ -7,14 +7,80 @@ pub trait Unpack {
         Self: Sized;
 }
 
+/// Every instruction that modifies an account expects the account first and its
+/// authority as a signer second.
 #[derive(BorshSerialize, BorshDeserialize)]
 #[borsh(use_discriminant = true)]
 #[repr(u8)]
 pub enum CounterInstruction {
-    InitCounter(u8),
+    /// Creates the vault PDA of the tree `tree_id` with `space` bytes of data, see
+    /// `state::find_vault_address` and `state::DEFAULT_ACCOUNT_SPACE`. The program
+    /// derives the canonical bump itself and stores it in the account. The tree starts
+    /// without leaves, its root is `merkle_tree::EMPTY_ROOT`. The account holds as many
+    /// leaves as fit after the header, `state::TreeAccount::space` gives the size for
+    /// a capacity.
+    InitTree {
+        tree_id: u64,
+        space: u64,
+    },
+    /// Expects a counter account created by `InitCounter`.
     IncCounter,
     DecCounter,
+    /// Appends a text leaf, see `AddLeafBytes` for other payloads.
     AddLeaf(String),
+    /// Rewrites a vault created with the old leaf layout. Expects the vault and its
+    /// creator as a signer.
+    MigrateLeafs,
+    /// Checks that `leaf` is stored at `index` under the account's `root_hash`.
+    /// `proof` holds the sibling hashes from the leaf level up, as returned by
+    /// `Proof::siblings`, and its length must match the depth of the stored tree.
+    /// The result is set as return data: `1` if the proof is valid, `0` otherwise.
+    /// Leaves are raw bytes, Borsh encodes a `String` the same way so text leaves are
+    /// sent unchanged.
+    VerifyLeaf {
+        leaf: Vec<u8>,
+        index: u64,
+        proof: Vec<Hash>,
+    },
+    /// Replaces the leaf at `index`.
+    UpdateLeaf {
+        index: u64,
+        new_leaf: Vec<u8>,
+    },
+    /// Replaces the leaf at `index` with `state::REMOVED_LEAF`, so the indexes of the
+    /// other leaves and their proofs stay valid.
+    RemoveLeaf {
+        index: u64,
+    },
+    /// Hands control of the account over to `new_authority`.
+    SetAuthority {
+        new_authority: Pubkey,
+    },
+    /// Reallocates the account data to `new_size` bytes. Expects a payer for the
+    /// rent and the system program after the authority. The data can grow by at most
+    /// `MAX_PERMITTED_DATA_INCREASE` bytes per instruction.
+    Resize {
+        new_size: u64,
+    },
+    /// Creates the counter PDA `counter_id`, see `state::find_counter_address`.
+    /// Expects the same accounts as `InitTree`.
+    InitCounter {
+        counter_id: u64,
+    },
+    /// Upgrades a tree or counter account to the current layout, see
+    /// `state::AccountState` and `state::TreeAccount`. Expects the account, its
+    /// authority as a signer that pays for any extra space, and the system program. A
+    /// migrated tree is sized for its existing leaves, `Resize` it to add more.
+    Migrate,
+    /// Appends the leaves in order and updates the root once. Leaves that don't fit
+    /// in the account are dropped, the number stored is returned as a little endian
+    /// `u64` in the return data. Fails with `AccountFull` if none fit, or `TreeFull`
+    /// once the tree holds `state::MAX_LEAVES`. Expects the same accounts as `AddLeaf`.
+    AddLeaves(Vec<Vec<u8>>),
+    /// Appends a leaf of arbitrary bytes, e.g. a raw hash or a Borsh encoded struct.
+    /// The leaf hash is computed the same way as for `AddLeaf`, so a text leaf added
+    /// either way ends up identical. Expects the same accounts as `AddLeaf`.
+    AddLeafBytes(Vec<u8>),
 }
 
 impl Unpack for CounterInstruction {
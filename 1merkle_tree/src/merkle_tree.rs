@@ -1,27 +1,157 @@
-use solana_program::hash::{hashv, Hash};
+use std::marker::PhantomData;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use solana_program::{
+    blake3,
+    hash::{hashv, Hash, HASH_BYTES},
+    keccak,
+};
 
 // We need to discern between leaf and intermediate nodes to prevent trivial second
 // pre-image attacks.
 // https://flawed.net.nz/2018/02/21/attacking-merkle-trees-with-a-second-preimage-attack
-const LEAF_PREFIX: &[u8] = &[0];
-const INTERMEDIATE_PREFIX: &[u8] = &[1];
+pub(crate) const LEAF_PREFIX: &[u8] = &[0];
+pub(crate) const INTERMEDIATE_PREFIX: &[u8] = &[1];
+
+/// Root of a tree without leaves: the SHA-256 of the empty string, as in RFC 6962.
+/// Neither a leaf nor an intermediate node can hash to it.
+pub const EMPTY_ROOT: Hash = Hash::new_from_array([
+    0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9, 0x24,
+    0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b, 0x78, 0x52, 0xb8, 0x55,
+]);
+
+/// Hashes a single leaf the way the tree does before inserting it.
+pub fn leaf_hash<T: AsRef<[u8]>>(item: T) -> Hash {
+    Sha256Hasher::hash_leaf(item.as_ref())
+}
+
+/// Hash function a `MerkleTree` is built with. Leaves and intermediate nodes get the
+/// same prefixes whatever the function, only `hashv` differs.
+///
+/// The program itself only verifies SHA-256 trees, the other hashers are for roots
+/// checked elsewhere, e.g. by an EVM contract.
+pub trait MerkleHasher {
+    fn hashv(vals: &[&[u8]]) -> Hash;
+
+    fn hash_leaf(data: &[u8]) -> Hash {
+        Self::hashv(&[LEAF_PREFIX, data])
+    }
+
+    fn hash_intermediate(left: &Hash, right: &Hash) -> Hash {
+        Self::hashv(&[INTERMEDIATE_PREFIX, left.as_ref(), right.as_ref()])
+    }
+
+    /// Root of a tree without leaves, the hash of the empty string.
+    fn empty_root() -> Hash {
+        Self::hashv(&[])
+    }
+
+    /// Recomputes the root for the leaf at `index` from its sibling hashes.
+    ///
+    /// The position of each sibling is taken from the bits of `index`, so `siblings`
+    /// must come from a tree of the same shape, e.g. `Proof::siblings`.
+    fn compute_root(leaf_hash: Hash, index: usize, siblings: &[Hash]) -> Hash {
+        let mut node_index = index;
+        siblings.iter().fold(leaf_hash, |node, sibling| {
+            let hash = if node_index % 2 == 1 {
+                Self::hash_intermediate(sibling, &node)
+            } else {
+                Self::hash_intermediate(&node, sibling)
+            };
+            node_index /= 2;
+            hash
+        })
+    }
+
+    /// Appends leaf number `leaf_count` to `frontier` and returns the new root. This is
+    /// what `IncrementalMerkleTree` does, over a frontier stored elsewhere, e.g. in the
+    /// account data.
+    ///
+    /// Panics if `frontier` is shorter than `leaf_count.trailing_ones() + 1`.
+    fn frontier_append(frontier: &mut [Hash], leaf_count: u64, leaf_hash: Hash) -> Hash {
+        Self::frontier_push(frontier, leaf_count, leaf_hash);
+        Self::frontier_root(frontier, leaf_count + 1, leaf_hash)
+    }
+
+    /// Same as `frontier_append` without computing the root, for appending several
+    /// leaves and calling `frontier_root` once after the last one.
+    fn frontier_push(frontier: &mut [Hash], leaf_count: u64, leaf_hash: Hash) {
+        let mut node = leaf_hash;
+        let mut node_index = leaf_count;
+        let mut level = 0;
+        while node_index % 2 == 1 {
+            node = Self::hash_intermediate(&frontier[level], &node);
+            node_index /= 2;
+            level += 1;
+        }
+        frontier[level] = node;
+    }
 
-macro_rules! hash_leaf {
-    {$d:ident} => {
-        hashv(&[LEAF_PREFIX, $d])
+    /// Root of a tree with `leaf_count` leaves, given its frontier and its last leaf.
+    fn frontier_root(frontier: &[Hash], leaf_count: u64, last_leaf_hash: Hash) -> Hash {
+        let mut node = last_leaf_hash;
+        let mut node_index = leaf_count - 1;
+        let mut level_len = leaf_count;
+        let mut level = 0;
+        while level_len > 1 {
+            node = if node_index % 2 == 1 {
+                Self::hash_intermediate(&frontier[level], &node)
+            } else {
+                // The right edge node is the last one on an odd length level, so it is
+                // paired with itself the same way `MerkleTree::new` does
+                Self::hash_intermediate(&node, &node)
+            };
+            node_index /= 2;
+            level_len = MerkleTree::next_level_len(level_len as usize) as u64;
+            level += 1;
+        }
+        node
     }
 }
 
-macro_rules! hash_intermediate {
-    {$l:ident, $r:ident} => {
-        hashv(&[INTERMEDIATE_PREFIX, $l.as_ref(), $r.as_ref()])
+/// SHA-256, the hash the program builds its trees with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sha256Hasher;
+
+impl MerkleHasher for Sha256Hasher {
+    fn hashv(vals: &[&[u8]]) -> Hash {
+        hashv(vals)
+    }
+}
+
+/// Keccak-256 as Ethereum's `keccak256`, so a contract can recompute a node with
+/// `keccak256(abi.encodePacked(bytes1(0x01), left, right))`.
+///
+/// The last node of an odd length level is paired with itself, so the proof of the
+/// last leaf also hashes to the root one index further. A contract checking proofs
+/// must require `index < leaf_count` and a proof length of
+/// `MerkleTree::tree_depth(leaf_count)`, as `OwnedProof::verify_with` does. Pairs
+/// keep their order, so OpenZeppelin's `MerkleProof`, which sorts each pair before
+/// hashing, can't check these proofs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Keccak256Hasher;
+
+impl MerkleHasher for Keccak256Hasher {
+    fn hashv(vals: &[&[u8]]) -> Hash {
+        Hash::new_from_array(keccak::hashv(vals).to_bytes())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Blake3Hasher;
+
+impl MerkleHasher for Blake3Hasher {
+    fn hashv(vals: &[&[u8]]) -> Hash {
+        Hash::new_from_array(blake3::hashv(vals).to_bytes())
     }
 }
 
 #[derive(Debug)]
-pub struct MerkleTree {
+pub struct MerkleTree<H = Sha256Hasher> {
     leaf_count: usize,
     nodes: Vec<Hash>,
+    hasher: PhantomData<H>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -39,18 +169,28 @@ impl<'a> ProofEntry<'a> {
 }
 
 #[derive(Debug, Default, PartialEq)]
-pub struct Proof<'a>(Vec<ProofEntry<'a>>);
+pub struct Proof<'a, H = Sha256Hasher>(Vec<ProofEntry<'a>>, PhantomData<H>);
 
-impl<'a> Proof<'a> {
+impl<'a, H: MerkleHasher> Proof<'a, H> {
     pub fn push(&mut self, entry: ProofEntry<'a>) {
         self.0.push(entry)
     }
 
+    /// Sibling hashes along the path, from the leaf level up to the root.
+    pub fn siblings(&self) -> Vec<Hash> {
+        self.0
+            .iter()
+            .map(|pe| *pe.1.or(pe.2).expect("proof entry has a sibling"))
+            .collect()
+    }
+
+    /// Checks that every step of the proof matches the intermediate hash stored in it.
+    /// This does not tie the proof to any root, use `verify_against_root` for that.
     pub fn verify(&self, candidate: Hash) -> bool {
         let result = self.0.iter().try_fold(candidate, |candidate, pe| {
             let lsib = pe.1.unwrap_or(&candidate);
             let rsib = pe.2.unwrap_or(&candidate);
-            let hash = hash_intermediate!(lsib, rsib);
+            let hash = H::hash_intermediate(lsib, rsib);
 
             if hash == *pe.0 {
                 Some(hash)
@@ -60,6 +200,190 @@ impl<'a> Proof<'a> {
         });
         matches!(result, Some(_))
     }
+
+    /// Recomputes the root for `leaf_data` at `index` from the sibling hashes alone
+    /// and compares it with the trusted `root` of a tree of `leaf_count` leaves.
+    pub fn verify_against_root(
+        &self,
+        leaf_data: &[u8],
+        index: usize,
+        leaf_count: usize,
+        root: &Hash,
+    ) -> bool {
+        OwnedProof::from(self).verify_with::<H>(leaf_data, index, leaf_count, root)
+    }
+}
+
+/// Proof that owns its hashes, so it can outlive the `MerkleTree` it came from and be
+/// sent over the wire or passed as instruction data. It hashes with SHA-256, as the
+/// program does.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, Default, PartialEq,
+)]
+pub struct OwnedProof {
+    /// Sibling hashes from the leaf level up to the root.
+    pub siblings: Vec<Hash>,
+    /// Bit `i` is set when `siblings[i]` is the left sibling.
+    pub left_bits: u64,
+}
+
+impl OwnedProof {
+    /// Longest path a proof can describe, one direction bit per level.
+    pub const MAX_DEPTH: usize = u64::BITS as usize;
+
+    pub fn depth(&self) -> usize {
+        self.siblings.len()
+    }
+
+    /// A proof decoded with Borsh or serde may be deeper than `MAX_DEPTH`, the methods
+    /// below reject it rather than read direction bits that don't exist.
+    fn is_too_deep(&self) -> bool {
+        self.depth() > OwnedProof::MAX_DEPTH
+    }
+
+    pub fn is_left_sibling(&self, level: usize) -> bool {
+        level < OwnedProof::MAX_DEPTH && self.left_bits & (1 << level) != 0
+    }
+
+    /// Hashes `leaf_hash` up the path and returns the resulting root, `None` if the
+    /// proof is deeper than `MAX_DEPTH`.
+    pub fn compute_root(&self, leaf_hash: Hash) -> Option<Hash> {
+        self.compute_root_with::<Sha256Hasher>(leaf_hash)
+    }
+
+    fn compute_root_with<H: MerkleHasher>(&self, leaf_hash: Hash) -> Option<Hash> {
+        Some(
+            *self
+                .targets_with::<H>(leaf_hash)?
+                .last()
+                .unwrap_or(&leaf_hash),
+        )
+    }
+
+    /// Intermediate hashes on the path of `leaf_hash`, the last one being the root.
+    pub fn targets(&self, leaf_hash: Hash) -> Option<Vec<Hash>> {
+        self.targets_with::<Sha256Hasher>(leaf_hash)
+    }
+
+    fn targets_with<H: MerkleHasher>(&self, leaf_hash: Hash) -> Option<Vec<Hash>> {
+        if self.is_too_deep() {
+            return None;
+        }
+        let mut node = leaf_hash;
+        let mut targets = Vec::with_capacity(self.depth());
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            node = if self.is_left_sibling(level) {
+                H::hash_intermediate(sibling, &node)
+            } else {
+                H::hash_intermediate(&node, sibling)
+            };
+            targets.push(node);
+        }
+        Some(targets)
+    }
+
+    /// Recomputes the root for `leaf_data` at `index` and compares it with the trusted
+    /// `root` of a tree of `leaf_count` leaves.
+    ///
+    /// The last node of an odd level is paired with itself, so the proof of the last
+    /// leaf also hashes to the root one index further. The proof is therefore only
+    /// accepted for an `index` below `leaf_count`, with the depth of such a tree and
+    /// direction bits that are the bits of `index`.
+    pub fn verify_against_root(
+        &self,
+        leaf_data: &[u8],
+        index: usize,
+        leaf_count: usize,
+        root: &Hash,
+    ) -> bool {
+        self.verify_with::<Sha256Hasher>(leaf_data, index, leaf_count, root)
+    }
+
+    /// Same as `verify_against_root` for a tree hashed with `H`.
+    pub fn verify_with<H: MerkleHasher>(
+        &self,
+        leaf_data: &[u8],
+        index: usize,
+        leaf_count: usize,
+        root: &Hash,
+    ) -> bool {
+        index < leaf_count
+            && self.depth() == MerkleTree::tree_depth(leaf_count)
+            && self.left_bits == index as u64
+            && self.compute_root_with::<H>(H::hash_leaf(leaf_data)) == Some(*root)
+    }
+
+    /// Borrowed view of the proof over `targets`, which should come from `targets`.
+    pub fn as_proof<'a>(&'a self, targets: &'a [Hash]) -> Proof<'a> {
+        let entries = self
+            .siblings
+            .iter()
+            .zip(targets)
+            .enumerate()
+            .map(|(level, (sibling, target))| {
+                if self.is_left_sibling(level) {
+                    ProofEntry::new(target, Some(sibling), None)
+                } else {
+                    ProofEntry::new(target, None, Some(sibling))
+                }
+            })
+            .collect();
+        Proof(entries, PhantomData)
+    }
+
+    /// Compact encoding: the depth as one byte, the direction bits packed into
+    /// `ceil(depth / 8)` bytes (least significant first), then the sibling hashes.
+    /// `None` if the proof is deeper than `MAX_DEPTH`.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        if self.is_too_deep() {
+            return None;
+        }
+        let depth = self.depth();
+        let bits_len = depth.div_ceil(8);
+        let mut bytes = Vec::with_capacity(1 + bits_len + depth * HASH_BYTES);
+        bytes.push(depth as u8);
+        bytes.extend_from_slice(&self.left_bits.to_le_bytes()[..bits_len]);
+        for sibling in &self.siblings {
+            bytes.extend_from_slice(sibling.as_ref());
+        }
+        Some(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&depth, rest) = bytes.split_first()?;
+        let depth = depth as usize;
+        if depth > OwnedProof::MAX_DEPTH {
+            return None;
+        }
+        let bits_len = depth.div_ceil(8);
+        if rest.len() != bits_len + depth * HASH_BYTES {
+            return None;
+        }
+        let (bits, hashes) = rest.split_at(bits_len);
+        let mut left_bits = [0u8; 8];
+        left_bits[..bits_len].copy_from_slice(bits);
+        Some(OwnedProof {
+            siblings: hashes.chunks(HASH_BYTES).map(Hash::new).collect(),
+            left_bits: u64::from_le_bytes(left_bits),
+        })
+    }
+}
+
+impl<'a, H> From<&Proof<'a, H>> for OwnedProof {
+    fn from(proof: &Proof<'a, H>) -> Self {
+        let mut owned = OwnedProof::default();
+        for (level, pe) in proof.0.iter().enumerate() {
+            match (pe.1, pe.2) {
+                (Some(lsib), _) => {
+                    owned.siblings.push(*lsib);
+                    owned.left_bits |= 1 << level;
+                }
+                (None, Some(rsib)) => owned.siblings.push(*rsib),
+                (None, None) => unreachable!("proof entry has a sibling"),
+            }
+        }
+        owned
+    }
 }
 
 impl MerkleTree {
@@ -95,22 +419,54 @@ impl MerkleTree {
         }
     }
 
+    /// Number of levels above the leaves, i.e. the length of every proof in a tree
+    /// with `leaf_count` leaves.
+    pub fn tree_depth(leaf_count: usize) -> usize {
+        let mut depth = 0;
+        let mut level_len = MerkleTree::next_level_len(leaf_count);
+        while level_len > 0 {
+            depth += 1;
+            level_len = MerkleTree::next_level_len(level_len);
+        }
+        depth
+    }
+
     pub fn new<T: AsRef<[u8]>>(items: &[T]) -> Self {
-        let cap = MerkleTree::calculate_vec_capacity(items.len());
+        MerkleTree::new_with_hasher(items)
+    }
+
+    /// Builds the tree from leaves that are already hashed with `leaf_hash`.
+    pub fn from_leaf_hashes(leaf_hashes: &[Hash]) -> Self {
+        MerkleTree::from_leaf_hashes_with_hasher(leaf_hashes)
+    }
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Same as `new`, hashing with `H`, e.g.
+    /// `MerkleTree::<Keccak256Hasher>::new_with_hasher(&items)`.
+    pub fn new_with_hasher<T: AsRef<[u8]>>(items: &[T]) -> Self {
+        let leaf_hashes: Vec<Hash> = items
+            .iter()
+            .map(|item| H::hash_leaf(item.as_ref()))
+            .collect();
+        Self::from_leaf_hashes_with_hasher(&leaf_hashes)
+    }
+
+    /// Builds the tree from leaves that are already hashed with `H::hash_leaf`.
+    pub fn from_leaf_hashes_with_hasher(leaf_hashes: &[Hash]) -> Self {
+        let leaf_count = leaf_hashes.len();
+        let cap = MerkleTree::calculate_vec_capacity(leaf_count);
         let mut mt = MerkleTree {
-            leaf_count: items.len(),
+            leaf_count,
             nodes: Vec::with_capacity(cap),
+            hasher: PhantomData,
         };
 
-        for item in items {
-            let item = item.as_ref();
-            let hash = hash_leaf!(item);
-            mt.nodes.push(hash);
-        }
+        mt.nodes.extend_from_slice(leaf_hashes);
 
-        let mut level_len = MerkleTree::next_level_len(items.len());
-        let mut level_start = items.len();
-        let mut prev_level_len = items.len();
+        let mut level_len = MerkleTree::next_level_len(leaf_count);
+        let mut level_start = leaf_count;
+        let mut prev_level_len = leaf_count;
         let mut prev_level_start = 0;
         while level_len > 0 {
             for i in 0..level_len {
@@ -123,7 +479,7 @@ impl MerkleTree {
                     &mt.nodes[prev_level_start + prev_level_idx]
                 };
 
-                let hash = hash_intermediate!(lsib, rsib);
+                let hash = H::hash_intermediate(lsib, rsib);
                 mt.nodes.push(hash);
             }
             prev_level_start = level_start;
@@ -139,14 +495,14 @@ impl MerkleTree {
         self.nodes.iter().last()
     }
 
-    pub fn find_path(&self, index: usize) -> Option<Proof> {
+    pub fn find_path(&self, index: usize) -> Option<Proof<H>> {
         if index >= self.leaf_count {
             return None;
         }
 
         let mut level_len = self.leaf_count;
         let mut level_start = 0;
-        let mut path = Proof::default();
+        let mut path = Proof(vec![], PhantomData);
         let mut node_index = index;
         let mut lsib = None;
         let mut rsib = None;
@@ -175,6 +531,223 @@ impl MerkleTree {
         }
         Some(path)
     }
+
+    /// Builds one proof for all leaves at `indices`. Siblings are only included when
+    /// they cannot be computed from the proven leaves, so shared nodes appear once.
+    pub fn find_multi_path(&self, indices: &[usize]) -> Option<MultiProof> {
+        if indices.is_empty() || indices.iter().any(|&index| index >= self.leaf_count) {
+            return None;
+        }
+
+        let mut known = indices.to_vec();
+        known.sort_unstable();
+        known.dedup();
+
+        let mut level_len = self.leaf_count;
+        let mut level_start = 0;
+        let mut proof = MultiProof::default();
+        while level_len > 1 {
+            let level = &self.nodes[level_start..(level_start + level_len)];
+
+            let mut parents = Vec::with_capacity(known.len());
+            let mut i = 0;
+            while i < known.len() {
+                let node_index = known[i];
+                let sibling_index = node_index ^ 1;
+                if i + 1 < known.len() && known[i + 1] == sibling_index {
+                    // both children are known, the verifier can hash them itself
+                    i += 1;
+                } else if sibling_index < level_len {
+                    proof.siblings.push(level[sibling_index]);
+                }
+                parents.push(node_index / 2);
+                i += 1;
+            }
+            known = parents;
+
+            level_start += level_len;
+            level_len = MerkleTree::next_level_len(level_len);
+        }
+        Some(proof)
+    }
+}
+
+/// Membership proof for several leaves of the same tree, see `MerkleTree::find_multi_path`.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, Default, PartialEq,
+)]
+pub struct MultiProof {
+    /// Sibling hashes in the order the verifier needs them: level by level from the
+    /// leaves up, left to right within a level.
+    pub siblings: Vec<Hash>,
+}
+
+impl MultiProof {
+    /// Checks every `(index, leaf_data)` pair against the trusted `root` of a tree with
+    /// `leaf_count` leaves. All siblings in the proof have to be used.
+    pub fn verify_against_root<T: AsRef<[u8]>>(
+        &self,
+        leaves: &[(usize, T)],
+        leaf_count: usize,
+        root: &Hash,
+    ) -> bool {
+        self.verify_with::<Sha256Hasher, T>(leaves, leaf_count, root)
+    }
+
+    /// Same as `verify_against_root` for a tree hashed with `H`.
+    pub fn verify_with<H: MerkleHasher, T: AsRef<[u8]>>(
+        &self,
+        leaves: &[(usize, T)],
+        leaf_count: usize,
+        root: &Hash,
+    ) -> bool {
+        if leaves.is_empty() || leaves.iter().any(|(index, _)| *index >= leaf_count) {
+            return false;
+        }
+
+        let mut known: Vec<(usize, Hash)> = leaves
+            .iter()
+            .map(|(index, leaf_data)| (*index, H::hash_leaf(leaf_data.as_ref())))
+            .collect();
+        known.sort_unstable_by_key(|(index, _)| *index);
+        // the same index may be listed twice, but only with the same leaf
+        if known
+            .windows(2)
+            .any(|w| w[0].0 == w[1].0 && w[0].1 != w[1].1)
+        {
+            return false;
+        }
+        known.dedup_by_key(|(index, _)| *index);
+
+        let mut siblings = self.siblings.iter();
+        let mut level_len = leaf_count;
+        while level_len > 1 {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut i = 0;
+            while i < known.len() {
+                let (node_index, node) = known[i];
+                let sibling_index = node_index ^ 1;
+                let parent = if i + 1 < known.len() && known[i + 1].0 == sibling_index {
+                    i += 1;
+                    H::hash_intermediate(&node, &known[i].1)
+                } else if sibling_index >= level_len {
+                    // Duplicate last entry if the level length is odd
+                    H::hash_intermediate(&node, &node)
+                } else {
+                    let Some(sibling) = siblings.next() else {
+                        return false;
+                    };
+                    if node_index % 2 == 1 {
+                        H::hash_intermediate(sibling, &node)
+                    } else {
+                        H::hash_intermediate(&node, sibling)
+                    }
+                };
+                parents.push((node_index / 2, parent));
+                i += 1;
+            }
+            known = parents;
+            level_len = MerkleTree::next_level_len(level_len);
+        }
+
+        siblings.next().is_none() && known[0].1 == *root
+    }
+}
+
+/// `MerkleHasher::compute_root` of a SHA-256 tree, as the program builds them.
+pub fn compute_root(leaf_hash: Hash, index: usize, siblings: &[Hash]) -> Hash {
+    Sha256Hasher::compute_root(leaf_hash, index, siblings)
+}
+
+/// Append-only Merkle tree that keeps only the right edge ("frontier") of the tree.
+///
+/// Appending a leaf and recomputing the root both take O(log n) hashes, and the root
+/// is the same as the one `MerkleTree::new` builds for the same items.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default, PartialEq)]
+pub struct IncrementalMerkleTree<H = Sha256Hasher> {
+    leaf_count: u64,
+    // frontier[level] is the last completed node with an even index on that level,
+    // i.e. the left sibling the next node on the level will be hashed with
+    frontier: Vec<Hash>,
+    root: Option<Hash>,
+    #[borsh(skip)]
+    hasher: PhantomData<H>,
+}
+
+impl IncrementalMerkleTree {
+    pub fn new<T: AsRef<[u8]>>(items: &[T]) -> Self {
+        IncrementalMerkleTree::new_with_hasher(items)
+    }
+
+    pub fn from_leaf_hashes(leaf_hashes: &[Hash]) -> Self {
+        IncrementalMerkleTree::from_leaf_hashes_with_hasher(leaf_hashes)
+    }
+}
+
+impl<H: MerkleHasher + Default> IncrementalMerkleTree<H> {
+    /// Same as `new`, hashing with `H`.
+    pub fn new_with_hasher<T: AsRef<[u8]>>(items: &[T]) -> Self {
+        let mut imt = Self::default();
+        for item in items {
+            imt.append(item);
+        }
+        imt
+    }
+
+    /// Builds the tree from leaves that are already hashed with `H::hash_leaf`.
+    pub fn from_leaf_hashes_with_hasher(leaf_hashes: &[Hash]) -> Self {
+        let mut imt = Self::default();
+        for leaf_hash in leaf_hashes {
+            imt.append_leaf_hash(*leaf_hash);
+        }
+        imt
+    }
+
+    pub fn leaf_count(&self) -> u64 {
+        self.leaf_count
+    }
+
+    pub fn get_root(&self) -> Option<&Hash> {
+        self.root.as_ref()
+    }
+
+    /// Same as `get_root`, with `H::empty_root` (`EMPTY_ROOT` for SHA-256) for a tree
+    /// without leaves.
+    pub fn root(&self) -> Hash {
+        self.root.unwrap_or_else(H::empty_root)
+    }
+
+    pub fn append<T: AsRef<[u8]>>(&mut self, item: T) {
+        self.append_leaf_hash(H::hash_leaf(item.as_ref()));
+    }
+
+    pub fn append_leaf_hash(&mut self, leaf_hash: Hash) {
+        let level = self.leaf_count.trailing_ones() as usize;
+        if level >= self.frontier.len() {
+            self.frontier.resize(level + 1, Hash::default());
+        }
+        self.root = Some(H::frontier_append(
+            &mut self.frontier,
+            self.leaf_count,
+            leaf_hash,
+        ));
+        self.leaf_count += 1;
+    }
+}
+
+/// `MerkleHasher::frontier_append` of a SHA-256 tree, as the program builds them.
+pub fn frontier_append(frontier: &mut [Hash], leaf_count: u64, leaf_hash: Hash) -> Hash {
+    Sha256Hasher::frontier_append(frontier, leaf_count, leaf_hash)
+}
+
+/// `MerkleHasher::frontier_push` of a SHA-256 tree.
+pub fn frontier_push(frontier: &mut [Hash], leaf_count: u64, leaf_hash: Hash) {
+    Sha256Hasher::frontier_push(frontier, leaf_count, leaf_hash)
+}
+
+/// `MerkleHasher::frontier_root` of a SHA-256 tree.
+pub fn frontier_root(frontier: &[Hash], leaf_count: u64, last_leaf_hash: Hash) -> Hash {
+    Sha256Hasher::frontier_root(frontier, leaf_count, last_leaf_hash)
 }
 
 #[cfg(test)]
@@ -197,7 +770,7 @@ mod tests {
     fn test_tree_from_one() {
         let input = b"test";
         let mt = MerkleTree::new(&[input]);
-        let expected = hash_leaf!(input);
+        let expected = leaf_hash(input);
         assert_eq!(mt.get_root(), Some(&expected));
     }
 
@@ -213,6 +786,13 @@ mod tests {
         assert_eq!(mt.get_root(), Some(&expected));
     }
 
+    #[test]
+    fn test_tree_from_leaf_hashes() {
+        let leaf_hashes: Vec<Hash> = TEST.iter().map(leaf_hash).collect();
+        let mt = MerkleTree::from_leaf_hashes(&leaf_hashes);
+        assert_eq!(mt.get_root(), MerkleTree::new(TEST).get_root());
+    }
+
     #[test]
     fn test_path_creation() {
         let mt = MerkleTree::new(TEST);
@@ -231,7 +811,7 @@ mod tests {
     fn test_path_verify_good() {
         let mt = MerkleTree::new(TEST);
         for (i, s) in TEST.iter().enumerate() {
-            let hash = hash_leaf!(s);
+            let hash = leaf_hash(s);
             let path = mt.find_path(i).unwrap();
             assert!(path.verify(hash));
         }
@@ -241,12 +821,236 @@ mod tests {
     fn test_path_verify_bad() {
         let mt = MerkleTree::new(TEST);
         for (i, s) in BAD.iter().enumerate() {
-            let hash = hash_leaf!(s);
+            let hash = leaf_hash(s);
             let path = mt.find_path(i).unwrap();
             assert!(!path.verify(hash));
         }
     }
 
+    #[test]
+    fn test_tree_depth() {
+        assert_eq!(MerkleTree::tree_depth(0), 0);
+        assert_eq!(MerkleTree::tree_depth(1), 0);
+        assert_eq!(MerkleTree::tree_depth(2), 1);
+        assert_eq!(MerkleTree::tree_depth(3), 2);
+        assert_eq!(MerkleTree::tree_depth(8), 3);
+        assert_eq!(MerkleTree::tree_depth(9), 4);
+
+        let mt = MerkleTree::new(TEST);
+        for i in 0..TEST.len() {
+            let depth = OwnedProof::from(&mt.find_path(i).unwrap()).depth();
+            assert_eq!(depth, MerkleTree::tree_depth(TEST.len()));
+        }
+    }
+
+    #[test]
+    fn test_path_verify_against_root_good() {
+        let mt = MerkleTree::new(TEST);
+        let root = mt.get_root().unwrap();
+        for (i, s) in TEST.iter().enumerate() {
+            let path = mt.find_path(i).unwrap();
+            assert!(path.verify_against_root(s, i, TEST.len(), root));
+        }
+    }
+
+    #[test]
+    fn test_path_verify_against_root_bad() {
+        let mt = MerkleTree::new(TEST);
+        let root = mt.get_root().unwrap();
+        for (i, s) in BAD.iter().enumerate() {
+            let path = mt.find_path(i).unwrap();
+            assert!(!path.verify_against_root(s, i, TEST.len(), root));
+        }
+
+        let path = mt.find_path(2).unwrap();
+        // right leaf, wrong position
+        assert!(!path.verify_against_root(TEST[2], 3, TEST.len(), root));
+        // right leaf and position, wrong root
+        assert!(!path.verify_against_root(TEST[2], 2, TEST.len(), &Hash::default()));
+    }
+
+    #[test]
+    fn test_path_verify_against_root_wrong_depth() {
+        let mt = MerkleTree::new(TEST);
+        let root = mt.get_root().unwrap();
+        let mut owned = OwnedProof::from(&mt.find_path(9).unwrap());
+        assert!(owned.verify_against_root(TEST[9], 9, TEST.len(), root));
+        // the root still matches, but no tree of that many leaves is this deep
+        assert!(!owned.verify_against_root(TEST[9], 9, 2 * TEST.len(), root));
+        owned.siblings.pop();
+        assert!(!owned.verify_against_root(TEST[9], 9, TEST.len(), root));
+    }
+
+    #[test]
+    fn test_path_verify_against_root_phantom_index() {
+        let leaves: &[&[u8]] = &[b"a", b"b", b"c"];
+        let mt = MerkleTree::new(leaves);
+        let root = mt.get_root().unwrap();
+        let mut owned = OwnedProof::from(&mt.find_path(2).unwrap());
+        // "c" is its own sibling, so its proof also hashes to the root at index 3
+        owned.left_bits = 3;
+        assert_eq!(owned.compute_root(leaf_hash(b"c")), Some(*root));
+        assert!(!owned.verify_against_root(b"c", 3, leaves.len(), root));
+    }
+
+    #[test]
+    fn test_single_leaf_verify_against_root() {
+        let mt = MerkleTree::new(&[b"test"]);
+        let root = mt.get_root().unwrap();
+        let path = mt.find_path(0).unwrap();
+        // the proof is empty, so the leaf alone has to hash to the root
+        assert!(path.verify_against_root(b"test", 0, 1, root));
+        assert!(!path.verify_against_root(b"other", 0, 1, root));
+        assert!(!path.verify_against_root(b"test", 1, 1, root));
+    }
+
+    #[test]
+    fn test_multi_path_verify_good() {
+        let mt = MerkleTree::new(TEST);
+        let root = mt.get_root().unwrap();
+        let index_sets: &[&[usize]] = &[
+            &[0],
+            &[10],
+            &[0, 1],
+            &[1, 2],
+            &[3, 9, 10],
+            &[10, 3, 9, 3],
+            &[0, 2, 4, 6, 8, 10],
+            &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+        ];
+        for indices in index_sets {
+            let proof = mt.find_multi_path(indices).unwrap();
+            let leaves: Vec<_> = indices.iter().map(|&i| (i, TEST[i])).collect();
+            assert!(
+                proof.verify_against_root(&leaves, TEST.len(), root),
+                "{:?}",
+                indices
+            );
+        }
+    }
+
+    #[test]
+    fn test_multi_path_verify_bad() {
+        let mt = MerkleTree::new(TEST);
+        let root = mt.get_root().unwrap();
+        let proof = mt.find_multi_path(&[1, 4, 7]).unwrap();
+
+        let good = [(1, TEST[1]), (4, TEST[4]), (7, TEST[7])];
+        assert!(!proof.verify_against_root(&good, 5, root));
+        assert!(!proof.verify_against_root(&good, 2 * TEST.len(), root));
+        assert!(!proof.verify_against_root(&good, TEST.len(), &Hash::default()));
+        assert!(!proof.verify_against_root(&good[..2], TEST.len(), root));
+
+        let bad = [(1, TEST[1]), (4, BAD[0]), (7, TEST[7])];
+        assert!(!proof.verify_against_root(&bad, TEST.len(), root));
+        let conflicting = [(1, TEST[1]), (4, TEST[4]), (4, BAD[0]), (7, TEST[7])];
+        assert!(!proof.verify_against_root(&conflicting, TEST.len(), root));
+
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert!(!short.verify_against_root(&good, TEST.len(), root));
+        let mut long = proof;
+        long.siblings.push(Hash::default());
+        assert!(!long.verify_against_root(&good, TEST.len(), root));
+    }
+
+    #[test]
+    fn test_multi_path_deduplicates_siblings() {
+        let mt = MerkleTree::new(TEST);
+        let single = mt.find_multi_path(&[5]).unwrap();
+        assert_eq!(single.siblings, mt.find_path(5).unwrap().siblings());
+
+        let indices = [0, 1, 2, 3];
+        let separate: usize = indices
+            .iter()
+            .map(|&i| mt.find_path(i).unwrap().siblings().len())
+            .sum();
+        let multi = mt.find_multi_path(&indices).unwrap();
+        assert!(multi.siblings.len() < separate);
+
+        let all: Vec<usize> = (0..TEST.len()).collect();
+        assert!(mt.find_multi_path(&all).unwrap().siblings.is_empty());
+    }
+
+    #[test]
+    fn test_multi_path_creation_bad_index() {
+        let mt = MerkleTree::new(TEST);
+        assert_eq!(mt.find_multi_path(&[]), None);
+        assert_eq!(mt.find_multi_path(&[0, TEST.len()]), None);
+    }
+
+    #[test]
+    fn test_compute_root_from_siblings() {
+        let mt = MerkleTree::new(TEST);
+        for (i, s) in TEST.iter().enumerate() {
+            let siblings = mt.find_path(i).unwrap().siblings();
+            assert_eq!(
+                compute_root(leaf_hash(s), i, &siblings),
+                *mt.get_root().unwrap()
+            );
+        }
+        for (i, s) in BAD.iter().enumerate() {
+            let siblings = mt.find_path(i).unwrap().siblings();
+            assert_ne!(
+                compute_root(leaf_hash(s), i, &siblings),
+                *mt.get_root().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_owned_proof_round_trip() {
+        let mt = MerkleTree::new(TEST);
+        for (i, s) in TEST.iter().enumerate() {
+            let path = mt.find_path(i).unwrap();
+            let owned = OwnedProof::from(&path);
+            assert_eq!(owned.siblings, path.siblings());
+            assert_eq!(owned.left_bits, i as u64);
+            assert_eq!(owned.compute_root(leaf_hash(s)), mt.get_root().copied());
+
+            let targets = owned.targets(leaf_hash(s)).unwrap();
+            assert_eq!(owned.as_proof(&targets), path);
+        }
+    }
+
+    #[test]
+    fn test_owned_proof_encoding() {
+        let mt = MerkleTree::new(TEST);
+        for i in 0..TEST.len() {
+            let owned = OwnedProof::from(&mt.find_path(i).unwrap());
+
+            let bytes = owned.to_bytes().unwrap();
+            assert_eq!(bytes.len(), 1 + 1 + owned.depth() * HASH_BYTES);
+            assert_eq!(OwnedProof::from_bytes(&bytes), Some(owned.clone()));
+
+            let borsh_bytes = borsh::to_vec(&owned).unwrap();
+            assert_eq!(OwnedProof::try_from_slice(&borsh_bytes).unwrap(), owned);
+        }
+    }
+
+    #[test]
+    fn test_owned_proof_from_bad_bytes() {
+        let owned = OwnedProof::from(&MerkleTree::new(TEST).find_path(3).unwrap());
+        let bytes = owned.to_bytes().unwrap();
+        assert_eq!(OwnedProof::from_bytes(&[]), None);
+        assert_eq!(OwnedProof::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(OwnedProof::from_bytes(&[65]), None);
+    }
+
+    #[test]
+    fn test_owned_proof_too_deep() {
+        // Borsh doesn't bound the number of siblings
+        let owned = OwnedProof {
+            siblings: vec![Hash::default(); OwnedProof::MAX_DEPTH + 1],
+            left_bits: u64::MAX,
+        };
+        let decoded = OwnedProof::try_from_slice(&borsh::to_vec(&owned).unwrap()).unwrap();
+        assert!(!decoded.is_left_sibling(OwnedProof::MAX_DEPTH));
+        assert_eq!(decoded.compute_root(leaf_hash(TEST[0])), None);
+        assert_eq!(decoded.targets(leaf_hash(TEST[0])), None);
+        assert_eq!(decoded.to_bytes(), None);
+    }
+
     #[test]
     fn test_proof_entry_instantiation_lsib_set() {
         ProofEntry::new(&Hash::default(), Some(&Hash::default()), None);
@@ -276,6 +1080,154 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_incremental_tree_from_empty() {
+        let imt = IncrementalMerkleTree::new::<[u8; 0]>(&[]);
+        assert_eq!(imt.get_root(), None);
+        assert_eq!(imt.root(), EMPTY_ROOT);
+        assert_eq!(imt.leaf_count(), 0);
+    }
+
+    #[test]
+    fn test_empty_root() {
+        assert_eq!(EMPTY_ROOT, hashv(&[]));
+        let imt = IncrementalMerkleTree::new(TEST);
+        assert_eq!(imt.root(), *imt.get_root().unwrap());
+    }
+
+    #[test]
+    fn test_incremental_tree_from_many() {
+        let imt = IncrementalMerkleTree::new(TEST);
+        let mt = MerkleTree::new(TEST);
+        assert_eq!(imt.get_root(), mt.get_root());
+        assert_eq!(imt.leaf_count(), TEST.len() as u64);
+    }
+
+    #[test]
+    fn test_incremental_tree_matches_full_rebuild() {
+        let items: Vec<[u8; 8]> = (0..300u64).map(u64::to_le_bytes).collect();
+        let mut imt: IncrementalMerkleTree = IncrementalMerkleTree::default();
+        for (i, item) in items.iter().enumerate() {
+            imt.append(item);
+            let mt = MerkleTree::new(&items[..=i]);
+            assert_eq!(imt.get_root(), mt.get_root(), "leaf count {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_fixed_frontier_matches_incremental_tree() {
+        let mut imt: IncrementalMerkleTree = IncrementalMerkleTree::default();
+        let mut frontier = [Hash::default(); 32];
+        for i in 0..100u64 {
+            let leaf = leaf_hash(i.to_le_bytes());
+            imt.append_leaf_hash(leaf);
+            let root = frontier_append(&mut frontier, i, leaf);
+            assert_eq!(Some(&root), imt.get_root());
+            assert_eq!(frontier_root(&frontier, i + 1, leaf), root);
+        }
+    }
+
+    #[test]
+    fn test_frontier_push_batch() {
+        let leaves: Vec<_> = TEST.iter().map(leaf_hash).collect();
+        let mut frontier = [Hash::default(); 32];
+        for (i, leaf) in leaves.iter().enumerate() {
+            frontier_push(&mut frontier, i as u64, *leaf);
+        }
+        let root = frontier_root(&frontier, leaves.len() as u64, *leaves.last().unwrap());
+        assert_eq!(Some(&root), MerkleTree::new(TEST).get_root());
+    }
+
+    #[test]
+    fn test_hasher_empty_roots() {
+        assert_eq!(Sha256Hasher::empty_root(), EMPTY_ROOT);
+        // keccak256("") as Solidity computes it
+        assert_eq!(
+            Keccak256Hasher::empty_root().to_bytes(),
+            hex_literal("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470")
+        );
+        assert_eq!(
+            Blake3Hasher::empty_root().to_bytes(),
+            hex_literal("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262")
+        );
+    }
+
+    #[test]
+    fn test_tree_with_hasher() {
+        let sha = MerkleTree::new(TEST);
+        let keccak = MerkleTree::<Keccak256Hasher>::new_with_hasher(TEST);
+        let blake3 = MerkleTree::<Blake3Hasher>::new_with_hasher(TEST);
+        assert_eq!(
+            sha.get_root(),
+            MerkleTree::<Sha256Hasher>::new_with_hasher(TEST).get_root()
+        );
+        assert_ne!(keccak.get_root(), sha.get_root());
+        assert_ne!(blake3.get_root(), sha.get_root());
+        assert_ne!(blake3.get_root(), keccak.get_root());
+
+        let root = *keccak.get_root().unwrap();
+        for (index, item) in TEST.iter().enumerate() {
+            let proof = keccak.find_path(index).unwrap();
+            assert!(proof.verify(Keccak256Hasher::hash_leaf(item)));
+            assert!(proof.verify_against_root(item, index, TEST.len(), &root));
+            assert_eq!(
+                Keccak256Hasher::compute_root(
+                    Keccak256Hasher::hash_leaf(item),
+                    index,
+                    &proof.siblings()
+                ),
+                root
+            );
+            // the same path hashed with SHA-256 doesn't lead to the root
+            let owned = OwnedProof::from(&proof);
+            assert!(owned.verify_with::<Keccak256Hasher>(item, index, TEST.len(), &root));
+            assert!(!owned.verify_against_root(item, index, TEST.len(), &root));
+        }
+    }
+
+    #[test]
+    fn test_keccak_proof_phantom_index() {
+        let leaves: &[&[u8]] = &[b"a", b"b", b"c"];
+        let mt = MerkleTree::<Keccak256Hasher>::new_with_hasher(leaves);
+        let root = mt.get_root().unwrap();
+        let proof = mt.find_path(2).unwrap();
+        assert!(proof.verify_against_root(b"c", 2, leaves.len(), root));
+        // "c" is its own sibling, so its proof also hashes to the root at index 3
+        let siblings = proof.siblings();
+        assert_eq!(
+            Keccak256Hasher::compute_root(Keccak256Hasher::hash_leaf(b"c"), 3, &siblings),
+            *root
+        );
+        let mut owned = OwnedProof::from(&proof);
+        owned.left_bits = 3;
+        assert!(!owned.verify_with::<Keccak256Hasher>(b"c", 3, leaves.len(), root));
+    }
+
+    #[test]
+    fn test_multi_proof_with_hasher() {
+        let mt = MerkleTree::<Keccak256Hasher>::new_with_hasher(TEST);
+        let root = mt.get_root().unwrap();
+        let indices = [1, 4, 10];
+        let proof = mt.find_multi_path(&indices).unwrap();
+        let leaves: Vec<_> = indices.iter().map(|&i| (i, TEST[i])).collect();
+        assert!(proof.verify_with::<Keccak256Hasher, _>(&leaves, TEST.len(), root));
+        assert!(!proof.verify_against_root(&leaves, TEST.len(), root));
+    }
+
+    #[test]
+    fn test_incremental_tree_with_hasher() {
+        let imt = IncrementalMerkleTree::<Blake3Hasher>::new_with_hasher(TEST);
+        let mt = MerkleTree::<Blake3Hasher>::new_with_hasher(TEST);
+        assert_eq!(imt.get_root(), mt.get_root());
+
+        let empty = IncrementalMerkleTree::<Blake3Hasher>::new_with_hasher::<[u8; 0]>(&[]);
+        assert_eq!(empty.root(), Blake3Hasher::empty_root());
+    }
+
+    fn hex_literal(hex: &str) -> [u8; 32] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
     #[test]
     #[should_panic]
     fn test_proof_entry_instantiation_both_clear() {
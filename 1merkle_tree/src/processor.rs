@@ -1,89 +1,189 @@
-use crate::{error::CustomError, merkle_tree::MerkleTree, state::CounterAccount};
-use borsh::BorshSerialize;
+use crate::{
+    error::CustomError,
+    events::MerkleEvent,
+    merkle_tree::{leaf_hash, MerkleTree, OwnedProof, EMPTY_ROOT},
+    state::{
+        find_counter_address, find_vault_address, AccountState, CounterAccount,
+        LegacyCounterAccount, TreeAccount, TreeAccountV1, COUNTER_SEED, MAX_LEAVES, REMOVED_LEAF,
+        VAULT_SEED,
+    },
+};
 use solana_program::{
-    account_info::next_account_info, account_info::AccountInfo, entrypoint::ProgramResult, msg,
-    program::invoke_signed, program_error::ProgramError, pubkey::Pubkey, rent::Rent,
-    system_instruction, sysvar::Sysvar,
+    account_info::next_account_info,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    hash::Hash,
+    msg,
+    program::{invoke, invoke_signed, set_return_data},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+    sysvar::Sysvar,
 };
 
-pub fn process_initialize_counter(
+// Dummy leaves and root that legacy vaults were initialized with.
+const PLACEHOLDER_LEAFS: [&str; 3] = ["args", "args", "args!"];
+const PLACEHOLDER_ROOT: [u8; 32] = [1; 32];
+
+/// Creates an empty tree in the vault of `tree_id`.
+pub fn process_initialize_tree(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    initial_value: u8,
+    tree_id: u64,
+    size_data: u64,
 ) -> ProgramResult {
     let accounts_iter = &mut accounts.iter();
 
-    let counter_account = next_account_info(accounts_iter)?;
+    let vault_account = next_account_info(accounts_iter)?;
     let payer_account = next_account_info(accounts_iter)?;
     let system_program = next_account_info(accounts_iter)?;
     msg!("system_program {}", system_program.key);
-    // let size_data = i64::BITS / 8;
-    let size_data = 10u64 * 1024;
 
-    let rent = Rent::get()?;
-
-    let required_lamports = rent.minimum_balance(size_data as usize);
-    msg!("minimum_balance {}", required_lamports);
+    // Only the canonical bump is accepted, so each (payer, tree_id) maps to one vault.
+    let (expected_vault_pda, bump) = find_vault_address(program_id, payer_account.key, tree_id);
+    if vault_account.key != &expected_vault_pda {
+        return Err(CustomError::InvalidPda.into());
+    }
 
-    // invoke(
-    //     &system_instruction::create_account(
-    //         payer_account.key,   //account paying for the new account
-    //         counter_account.key, //account to be created
-    //         required_lamports,   // amount of lamport given to the new account
-    //         size_data,           //size in bytes to allocate for the data field
-    //         program_id,          //program owner is set to our program
-    //     ),
-    //     &[
-    //         payer_account.clone(),
-    //         counter_account.clone(),
-    //         system_program.clone(),
-    //     ],
-    // )?;
-
-    assert!(payer_account.is_writable);
-    assert!(payer_account.is_signer);
-    assert!(counter_account.is_writable);
-    assert_eq!(counter_account.owner, &solana_program::system_program::ID); //владелец 111 то есть никто
-    assert!(solana_program::system_program::check_id(system_program.key));
-
-    let vault_bump_seed = initial_value;
-    let vault_seeds = &[b"vault", payer_account.key.as_ref(), &[vault_bump_seed]];
-    let expected_vault_pda = Pubkey::create_program_address(vault_seeds, program_id)?;
-
-    assert_eq!(counter_account.key, &expected_vault_pda);
+    if size_data < TreeAccount::space(0) {
+        return Err(CustomError::AccountFull.into());
+    }
 
-    invoke_signed(
-        &system_instruction::create_account(
-            payer_account.key,   //account paying for the new account
-            counter_account.key, //account to be created
-            required_lamports,   // amount of lamport given to the new account
-            size_data.into(),    //size in bytes to allocate for the data field
-            program_id,          //program owner is set to our program
-        ),
+    create_pda_account(
+        program_id,
+        vault_account,
+        payer_account,
+        system_program,
+        size_data,
         &[
-            payer_account.clone(),
-            counter_account.clone(),
-            system_program.clone(),
+            VAULT_SEED,
+            payer_account.key.as_ref(),
+            &tree_id.to_le_bytes(),
+            &[bump],
         ],
-        // A slice of seed slices, each seed slice being the set
-        // of seeds used to generate one of the PDAs required by the
-        // callee program, the final seed being a single-element slice
-        // containing the `u8` bump seed.
-        &[&[b"vault", payer_account.key.as_ref(), &[initial_value]]],
     )?;
 
+    TreeAccount::init(
+        &mut vault_account.data.borrow_mut(),
+        *payer_account.key,
+        Some(tree_id),
+        bump,
+        *payer_account.key,
+        &[],
+    )?;
+    msg!("tree {} created with bump {}", tree_id, bump);
+    Ok(())
+}
+
+/// Creates the counter `counter_id`, starting at zero.
+pub fn process_initialize_counter(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    counter_id: u64,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let counter_account = next_account_info(accounts_iter)?;
+    let payer_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    let (expected_counter_pda, bump) =
+        find_counter_address(program_id, payer_account.key, counter_id);
+    if counter_account.key != &expected_counter_pda {
+        return Err(CustomError::InvalidPda.into());
+    }
+
     let counter_data = CounterAccount {
-        count: initial_value.into(),
-        root_hash: [1; 32].into(),
-        leafs: vec!["args".to_string(), "args".to_string(), "args!".to_string()],
+        creator: *payer_account.key,
+        counter_id,
+        bump,
+        authority: *payer_account.key,
+        count: 0,
     };
 
-    let mut account_data = &mut counter_account.data.borrow_mut()[..];
-    counter_data.serialize(&mut account_data)?;
-    msg!("counter init to {}", initial_value);
+    create_pda_account(
+        program_id,
+        counter_account,
+        payer_account,
+        system_program,
+        counter_data.packed_len()? as u64,
+        &[
+            COUNTER_SEED,
+            payer_account.key.as_ref(),
+            &counter_id.to_le_bytes(),
+            &[bump],
+        ],
+    )?;
+
+    counter_data.pack(&mut counter_account.data.borrow_mut())?;
+    msg!("counter {} created with bump {}", counter_id, bump);
+    Ok(())
+}
+
+/// Upgrades a tree or counter account to the current layout:
+/// - accounts written before the `AccountHeader` existed (version 0) get one,
+/// - trees stored with Borsh (version 1) move to the zero-copy `TreeAccount` layout.
+///
+/// The account grows if the new layout needs more space, with the authority paying
+/// the rent.
+pub fn migrate(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    if account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let (authority, migrated) = {
+        let data = account.data.borrow();
+        if TreeAccount::load(&data).is_ok() || CounterAccount::unpack(&data).is_ok() {
+            msg!("account is up to date");
+            return Ok(());
+        }
+
+        if let Ok(tree_data) = load_state::<TreeAccountV1>(program_id, account.key, &data) {
+            (tree_data.authority, Migrated::Tree(tree_data))
+        } else if let Some(tree_data) =
+            load_headerless::<TreeAccountV1>(program_id, account.key, &data)
+        {
+            (tree_data.authority, Migrated::Tree(tree_data))
+        } else if let Some(counter_data) =
+            load_headerless::<CounterAccount>(program_id, account.key, &data)
+        {
+            (counter_data.authority, Migrated::Counter(counter_data))
+        } else {
+            return Err(CustomError::AccountDataCorrupt.into());
+        }
+    };
+    check_authority(&authority, authority_account)?;
+
+    let new_len = match &migrated {
+        Migrated::Tree(tree_data) => TreeAccount::space(tree_data.leafs.len() as u64) as usize,
+        Migrated::Counter(counter_data) => counter_data.packed_len()?,
+    };
+    if new_len > account.data_len() {
+        realloc_account(account, authority_account, system_program, new_len)?;
+    }
+
+    let mut data = account.data.borrow_mut();
+    match migrated {
+        Migrated::Tree(tree_data) => write_tree_v1(&mut data, tree_data)?,
+        Migrated::Counter(counter_data) => counter_data.pack(&mut data)?,
+    }
+    msg!("account migrated");
+
     Ok(())
 }
 
+enum Migrated {
+    Tree(TreeAccountV1),
+    Counter(CounterAccount),
+}
+
 pub fn process_change_counter(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
@@ -92,6 +192,7 @@ pub fn process_change_counter(
     let accounts_iter = &mut accounts.iter();
 
     let counter_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
 
     if counter_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
@@ -100,8 +201,8 @@ pub fn process_change_counter(
     let mut data = counter_account.data.borrow_mut();
     // let data = counter_account.data.borrow();
     // let mut counter_data: CounterAccount = CounterAccount::try_from_slice(&data)?;
-    let mut counter_data =
-        solana_program::borsh1::try_from_slice_unchecked::<CounterAccount>(&data).unwrap();
+    let mut counter_data = load_state::<CounterAccount>(program_id, counter_account.key, &data)?;
+    check_authority(&counter_data.authority, authority_account)?;
     match inc {
         true => {
             counter_data.count = counter_data
@@ -117,34 +218,482 @@ pub fn process_change_counter(
         }
     };
 
-    counter_data.serialize(&mut &mut data[..])?;
+    counter_data.pack(&mut data)?;
     msg!("counter incremented to: {}", counter_data.count);
     Ok(())
 }
 
-pub fn add_leaf(program_id: &Pubkey, accounts: &[AccountInfo], leaf: String) -> ProgramResult {
-    msg!("add_leaf: {}", leaf);
+pub fn add_leaf(program_id: &Pubkey, accounts: &[AccountInfo], leaf: &[u8]) -> ProgramResult {
+    msg!("add_leaf: {} bytes", leaf.len());
 
     let accounts_iter = &mut accounts.iter();
 
-    let counter_account = next_account_info(accounts_iter)?;
+    let vault_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
 
-    if counter_account.owner != program_id {
+    if vault_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    let mut data = counter_account.data.borrow_mut();
-    let mut counter_data =
-        solana_program::borsh1::try_from_slice_unchecked::<CounterAccount>(&data).unwrap();
+    let mut data = vault_account.data.borrow_mut();
+    let (tree_data, slots) = load_tree_mut(program_id, vault_account.key, &mut data)?;
+    check_authority(&tree_data.authority, authority_account)?;
+
+    let hash = leaf_hash(leaf);
+    tree_data.append(slots, hash)?;
+
+    msg!("leaf_hash: {}", hash);
+    msg!("root_hash: {}", tree_data.root_hash);
+    MerkleEvent::LeafAdded {
+        tree: *vault_account.key,
+        index: tree_data.leaf_count() - 1,
+        leaf: leaf.to_vec(),
+        leaf_hash: hash,
+        new_root: tree_data.root_hash,
+        leaf_count: tree_data.leaf_count(),
+    }
+    .emit();
+
+    Ok(())
+}
+
+pub fn add_leaves(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mut leaves: Vec<Vec<u8>>,
+) -> ProgramResult {
+    msg!("add_leaves: {}", leaves.len());
+
+    let accounts_iter = &mut accounts.iter();
+
+    let vault_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    if vault_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut data = vault_account.data.borrow_mut();
+    let (tree_data, slots) = load_tree_mut(program_id, vault_account.key, &mut data)?;
+    check_authority(&tree_data.authority, authority_account)?;
+
+    let mut hashes: Vec<Hash> = leaves.iter().map(leaf_hash).collect();
+    let start_index = tree_data.leaf_count();
+    let accepted = tree_data.append_many(slots, &hashes);
+    if accepted == 0 && !hashes.is_empty() {
+        if tree_data.leaf_count() >= MAX_LEAVES {
+            return Err(CustomError::TreeFull.into());
+        }
+        return Err(CustomError::AccountFull.into());
+    }
+
+    set_return_data(&(accepted as u64).to_le_bytes());
+    msg!("accepted: {}", accepted);
+    msg!("root_hash: {}", tree_data.root_hash);
+    if accepted > 0 {
+        leaves.truncate(accepted);
+        hashes.truncate(accepted);
+        MerkleEvent::LeavesAdded {
+            tree: *vault_account.key,
+            start_index,
+            leaves,
+            leaf_hashes: hashes,
+            new_root: tree_data.root_hash,
+            leaf_count: tree_data.leaf_count(),
+        }
+        .emit();
+    }
+
+    Ok(())
+}
+
+/// Rewrites an account created with the old `Vec<String>` leaf layout so that it
+/// stores leaf hashes instead. The creator of the account becomes its authority.
+pub fn migrate_leafs(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
 
-    counter_data.leafs.push(leaf);
+    let vault_account = next_account_info(accounts_iter)?;
+    let creator_account = next_account_info(accounts_iter)?;
 
-    let tree = MerkleTree::new(&counter_data.leafs);
+    if vault_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let bump = check_creator(program_id, vault_account, creator_account)?;
 
-    counter_data.root_hash = *tree.get_root().unwrap();
+    let mut data = vault_account.data.borrow_mut();
+    let mut legacy_data =
+        solana_program::borsh1::try_from_slice_unchecked::<LegacyCounterAccount>(&data)
+            .map_err(|_| ProgramError::InvalidAccountData)?;
 
-    counter_data.serialize(&mut &mut data[..])?;
-    msg!("root_hash: {}", counter_data.root_hash);
+    // An account in the new layout can still parse as the legacy one, so make sure the
+    // leaves really belong to the stored root (or are the untouched init placeholders)
+    // before overwriting anything.
+    let is_placeholder =
+        legacy_data.root_hash == PLACEHOLDER_ROOT.into() && legacy_data.leafs == PLACEHOLDER_LEAFS;
+    let tree = MerkleTree::new(&legacy_data.leafs);
+    if !is_placeholder && tree.get_root() != Some(&legacy_data.root_hash) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if is_placeholder {
+        // the placeholders were never real leaves, the vault becomes an empty tree
+        legacy_data.leafs.clear();
+        legacy_data.root_hash = EMPTY_ROOT;
+    }
+
+    let tree_data = legacy_data.into_current(*creator_account.key, bump);
+    let leaf_count = tree_data.leafs.len();
+    write_tree_v1(&mut data, tree_data)?;
+    msg!("migrated {} leafs", leaf_count);
 
     Ok(())
 }
+
+pub fn verify_leaf(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    leaf: Vec<u8>,
+    index: u64,
+    proof: Vec<Hash>,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let vault_account = next_account_info(accounts_iter)?;
+
+    if vault_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let data = vault_account.data.borrow();
+    let (tree_data, leafs) = load_tree(program_id, vault_account.key, &data)?;
+
+    let is_valid = OwnedProof {
+        siblings: proof,
+        left_bits: index,
+    }
+    .verify_against_root(&leaf, index as usize, leafs.len(), &tree_data.root_hash);
+
+    // Return data lets programs calling through CPI read the result instead of
+    // having to handle a failed instruction.
+    set_return_data(&[is_valid.into()]);
+    msg!("verify_leaf: {}", is_valid);
+
+    Ok(())
+}
+
+pub fn update_leaf(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    index: u64,
+    new_leaf: Vec<u8>,
+) -> ProgramResult {
+    msg!("update_leaf {}: {} bytes", index, new_leaf.len());
+    let hash = leaf_hash(&new_leaf);
+    let (tree, new_root) = replace_leaf(program_id, accounts, index, hash)?;
+    MerkleEvent::LeafUpdated {
+        tree,
+        index,
+        leaf: new_leaf,
+        leaf_hash: hash,
+        new_root,
+    }
+    .emit();
+    Ok(())
+}
+
+pub fn remove_leaf(program_id: &Pubkey, accounts: &[AccountInfo], index: u64) -> ProgramResult {
+    msg!("remove_leaf {}", index);
+    let (tree, new_root) = replace_leaf(program_id, accounts, index, REMOVED_LEAF)?;
+    MerkleEvent::LeafRemoved {
+        tree,
+        index,
+        new_root,
+    }
+    .emit();
+    Ok(())
+}
+
+/// Sets the leaf at `index` to `hash` and returns the vault address and the new root.
+fn replace_leaf(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    index: u64,
+    hash: Hash,
+) -> Result<(Pubkey, Hash), ProgramError> {
+    let accounts_iter = &mut accounts.iter();
+
+    let vault_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    if vault_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut data = vault_account.data.borrow_mut();
+    let (tree_data, slots) = load_tree_mut(program_id, vault_account.key, &mut data)?;
+    check_authority(&tree_data.authority, authority_account)?;
+
+    let leafs = &mut slots[..tree_data.leaf_count() as usize];
+    let leaf = leafs
+        .get_mut(index as usize)
+        .ok_or(CustomError::InvalidLeafIndex)?;
+    *leaf = hash;
+    tree_data.rebuild(leafs);
+
+    msg!("leaf_hash: {}", hash);
+    msg!("root_hash: {}", tree_data.root_hash);
+
+    Ok((*vault_account.key, tree_data.root_hash))
+}
+
+/// Reallocates the vault to `new_size` bytes, see `realloc_account`.
+pub fn resize(program_id: &Pubkey, accounts: &[AccountInfo], new_size: u64) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let vault_account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+    let payer_account = next_account_info(accounts_iter)?;
+    let system_program = next_account_info(accounts_iter)?;
+
+    if vault_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    {
+        let data = vault_account.data.borrow();
+        let (tree_data, leafs) = load_tree(program_id, vault_account.key, &data)?;
+        check_authority(&tree_data.authority, authority_account)?;
+        if TreeAccount::space(leafs.len() as u64) > new_size {
+            return Err(CustomError::AccountFull.into());
+        }
+    }
+
+    realloc_account(
+        vault_account,
+        payer_account,
+        system_program,
+        new_size as usize,
+    )?;
+    msg!("account resized to {}", new_size);
+
+    Ok(())
+}
+
+/// Works on trees and counters, told apart by their discriminator.
+pub fn set_authority(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_authority: Pubkey,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let account = next_account_info(accounts_iter)?;
+    let authority_account = next_account_info(accounts_iter)?;
+
+    if account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut data = account.data.borrow_mut();
+    if data.starts_with(&CounterAccount::DISCRIMINATOR) {
+        let mut counter_data = load_state::<CounterAccount>(program_id, account.key, &data)?;
+        check_authority(&counter_data.authority, authority_account)?;
+        counter_data.authority = new_authority;
+        counter_data.pack(&mut data)?;
+    } else {
+        let (tree_data, _) = load_tree_mut(program_id, account.key, &mut data)?;
+        check_authority(&tree_data.authority, authority_account)?;
+        tree_data.authority = new_authority;
+    }
+
+    msg!("authority set to {}", new_authority);
+
+    Ok(())
+}
+
+// Like `load_state`, for the zero-copy tree layout.
+fn load_tree<'a>(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    data: &'a [u8],
+) -> Result<(&'a TreeAccount, &'a [Hash]), ProgramError> {
+    let (tree_data, leafs) = TreeAccount::load(data)?;
+    if tree_data.find_vault_address(program_id) != (*vault, tree_data.bump) {
+        return Err(CustomError::InvalidPda.into());
+    }
+    Ok((tree_data, leafs))
+}
+
+fn load_tree_mut<'a>(
+    program_id: &Pubkey,
+    vault: &Pubkey,
+    data: &'a mut [u8],
+) -> Result<(&'a mut TreeAccount, &'a mut [Hash]), ProgramError> {
+    let (tree_data, slots) = TreeAccount::load_mut(data)?;
+    if tree_data.find_vault_address(program_id) != (*vault, tree_data.bump) {
+        return Err(CustomError::InvalidPda.into());
+    }
+    Ok((tree_data, slots))
+}
+
+// Rewrites a tree from the Borsh layout into the zero-copy one.
+fn write_tree_v1(data: &mut [u8], tree_data: TreeAccountV1) -> ProgramResult {
+    if TreeAccount::space(tree_data.leafs.len() as u64) > data.len() as u64 {
+        return Err(CustomError::AccountFull.into());
+    }
+    TreeAccount::init(
+        data,
+        tree_data.creator,
+        tree_data.tree_id,
+        tree_data.bump,
+        tree_data.authority,
+        &tree_data.leafs,
+    )
+}
+
+// Besides parsing the data, checks that the account sits at the canonical address of
+// the seeds it stores.
+fn load_state<T: AccountState>(
+    program_id: &Pubkey,
+    address: &Pubkey,
+    data: &[u8],
+) -> Result<T, ProgramError> {
+    let state = T::unpack(data)?;
+    if state.find_address(program_id) != (*address, state.bump()) {
+        return Err(CustomError::InvalidPda.into());
+    }
+    Ok(state)
+}
+
+// Parses state written before the `AccountHeader` was introduced (layout version 0),
+// as long as it belongs at `address`.
+fn load_headerless<T: AccountState>(
+    program_id: &Pubkey,
+    address: &Pubkey,
+    data: &[u8],
+) -> Option<T> {
+    let state = solana_program::borsh1::try_from_slice_unchecked::<T>(data).ok()?;
+    (state.find_address(program_id) == (*address, state.bump())).then_some(state)
+}
+
+// Reallocates `account` to `new_size` bytes, keeping it rent exempt. The payer tops up
+// the rent when the account grows and gets the excess back when it shrinks.
+fn realloc_account<'a>(
+    account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    new_size: usize,
+) -> ProgramResult {
+    if !payer_account.is_writable || !account.is_writable {
+        return Err(CustomError::NotWritable.into());
+    }
+    if !payer_account.is_signer {
+        return Err(CustomError::NotSigner.into());
+    }
+    if !solana_program::system_program::check_id(system_program.key) {
+        return Err(CustomError::InvalidSystemProgram.into());
+    }
+
+    let required_lamports = Rent::get()?.minimum_balance(new_size);
+    let current_lamports = account.lamports();
+    if required_lamports > current_lamports {
+        invoke(
+            &system_instruction::transfer(
+                payer_account.key,
+                account.key,
+                required_lamports - current_lamports,
+            ),
+            &[
+                payer_account.clone(),
+                account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    }
+
+    account.realloc(new_size, true)?;
+
+    if current_lamports > required_lamports {
+        let excess = current_lamports - required_lamports;
+        **account.try_borrow_mut_lamports()? -= excess;
+        **payer_account.try_borrow_mut_lamports()? += excess;
+    }
+    Ok(())
+}
+
+// Creates `new_account` as a PDA of the program, signed with `seeds`.
+fn create_pda_account<'a>(
+    program_id: &Pubkey,
+    new_account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    size_data: u64,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    if !payer_account.is_writable || !new_account.is_writable {
+        return Err(CustomError::NotWritable.into());
+    }
+    if !payer_account.is_signer {
+        return Err(CustomError::NotSigner.into());
+    }
+    if new_account.owner != &solana_program::system_program::ID {
+        //владелец 111 то есть никто
+        return Err(CustomError::AlreadyInitialized.into());
+    }
+    if !solana_program::system_program::check_id(system_program.key) {
+        return Err(CustomError::InvalidSystemProgram.into());
+    }
+
+    let rent = Rent::get()?;
+
+    let required_lamports = rent.minimum_balance(size_data as usize);
+    msg!("minimum_balance {}", required_lamports);
+
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_account.key, //account paying for the new account
+            new_account.key,   //account to be created
+            required_lamports, // amount of lamport given to the new account
+            size_data,         //size in bytes to allocate for the data field
+            program_id,        //program owner is set to our program
+        ),
+        &[
+            payer_account.clone(),
+            new_account.clone(),
+            system_program.clone(),
+        ],
+        // A slice of seed slices, each seed slice being the set
+        // of seeds used to generate one of the PDAs required by the
+        // callee program, the final seed being a single-element slice
+        // containing the `u8` bump seed.
+        &[seeds],
+    )
+}
+
+fn check_authority(authority: &Pubkey, authority_account: &AccountInfo) -> ProgramResult {
+    if !authority_account.is_signer {
+        return Err(CustomError::NotSigner.into());
+    }
+    if authority_account.key != authority {
+        return Err(CustomError::Unauthorized.into());
+    }
+    Ok(())
+}
+
+/// Checks that `creator_account` signed and that `vault_account` is its vault.
+/// Legacy vaults predate tree ids and only use the creator as seed.
+/// Returns the canonical bump of the vault.
+fn check_creator(
+    program_id: &Pubkey,
+    vault_account: &AccountInfo,
+    creator_account: &AccountInfo,
+) -> Result<u8, ProgramError> {
+    if !creator_account.is_signer {
+        return Err(CustomError::NotSigner.into());
+    }
+    let (expected_vault_pda, bump) =
+        Pubkey::find_program_address(&[VAULT_SEED, creator_account.key.as_ref()], program_id);
+    if vault_account.key != &expected_vault_pda {
+        return Err(CustomError::Unauthorized.into());
+    }
+    Ok(bump)
+}
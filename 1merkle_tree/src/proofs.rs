@@ -0,0 +1,311 @@
+use std::{path::Path, str::FromStr};
+
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+
+use solana_program::hash::Hash;
+
+use crate::merkle_tree::{MerkleHasher, MerkleTree, Sha256Hasher};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ProofsError {
+    #[error("Unknown leaf file format {0}, expected csv, json or lines")]
+    UnknownFormat(String),
+    #[error("Column {0} not found")]
+    MissingColumn(String),
+    #[error("Line {line} has no column {column}")]
+    ShortRow { line: usize, column: usize },
+    #[error("Invalid JSON: {0}")]
+    InvalidJson(String),
+    #[error("Entry {0} is not a string or a number")]
+    InvalidEntry(usize),
+}
+
+/// Layout of a file of leaves, one leaf per line, row or array entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LeafFormat {
+    /// Comma separated values with a header row. The leaf is one column of each row.
+    Csv,
+    /// An array of strings, or of objects with the leaf in one of their fields.
+    Json,
+    /// Every non-empty line is a leaf.
+    Lines,
+}
+
+impl LeafFormat {
+    /// Guesses the format from the extension of `path`, defaulting to `Lines`.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => LeafFormat::Csv,
+            Some(ext) if ext.eq_ignore_ascii_case("json") => LeafFormat::Json,
+            _ => LeafFormat::Lines,
+        }
+    }
+}
+
+impl FromStr for LeafFormat {
+    type Err = ProofsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(LeafFormat::Csv),
+            "json" => Ok(LeafFormat::Json),
+            "lines" => Ok(LeafFormat::Lines),
+            _ => Err(ProofsError::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// Reads the leaves out of `content`, in file order. `column` names the CSV column
+/// or the JSON field holding the leaf, the first column is used if `None`.
+pub fn read_leaves(
+    content: &str,
+    format: LeafFormat,
+    column: Option<&str>,
+) -> Result<Vec<String>, ProofsError> {
+    match format {
+        LeafFormat::Csv => read_csv(content, column),
+        LeafFormat::Json => read_json(content, column),
+        LeafFormat::Lines => Ok(content
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect()),
+    }
+}
+
+/// How hashes are written in a `ProofsFile`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashEncoding {
+    /// As `merkle-cli verify --proof` takes them.
+    Base58,
+    /// `0x` prefixed hex, as EVM tooling takes `bytes32` values.
+    Hex,
+}
+
+impl HashEncoding {
+    pub fn encode(&self, hash: &Hash) -> String {
+        match self {
+            HashEncoding::Base58 => hash.to_string(),
+            HashEncoding::Hex => format!("0x{}", hex::encode(hash)),
+        }
+    }
+}
+
+/// Proof of one leaf, with hashes written in the `HashEncoding` of the file.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LeafProof {
+    pub index: u64,
+    /// The leaf as read from the file.
+    pub leaf: String,
+    pub leaf_hash: String,
+    /// Sibling hashes in the order `CounterInstruction::VerifyLeaf` expects them.
+    pub proof: Vec<String>,
+}
+
+/// Proofs of every leaf of one tree.
+///
+/// The last node of an odd length level is paired with itself, so the proof of the
+/// last leaf also hashes to the root one index further. A verifier must therefore
+/// check `index < leaf_count` and that the proof holds `depth` hashes.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ProofsFile {
+    pub root: String,
+    pub leaf_count: u64,
+    /// Length of every proof, `MerkleTree::tree_depth(leaf_count)`.
+    pub depth: u64,
+    pub proofs: Vec<LeafProof>,
+}
+
+/// Builds the tree of `leaves` and the proof of each of them, in base58. `leaves`
+/// holds the leaves as read from the file next to the bytes that get hashed, since
+/// the two differ for hex encoded leaves.
+pub fn build_proofs<T: AsRef<[u8]>>(leaves: &[(String, T)]) -> ProofsFile {
+    build_proofs_with_hasher::<Sha256Hasher, T>(leaves, HashEncoding::Base58)
+}
+
+/// Same as `build_proofs` for a tree hashed with `H`, written with `encoding`. Only
+/// SHA-256 proofs are accepted by the program.
+pub fn build_proofs_with_hasher<H: MerkleHasher, T: AsRef<[u8]>>(
+    leaves: &[(String, T)],
+    encoding: HashEncoding,
+) -> ProofsFile {
+    let leaf_hashes: Vec<_> = leaves
+        .iter()
+        .map(|(_, bytes)| H::hash_leaf(bytes.as_ref()))
+        .collect();
+    let tree = MerkleTree::<H>::from_leaf_hashes_with_hasher(&leaf_hashes);
+    let proofs = leaves
+        .iter()
+        .zip(&leaf_hashes)
+        .enumerate()
+        .map(|(index, ((leaf, _), leaf_hash))| LeafProof {
+            index: index as u64,
+            leaf: leaf.clone(),
+            leaf_hash: encoding.encode(leaf_hash),
+            proof: tree
+                .find_path(index)
+                .map(|proof| proof.siblings())
+                .unwrap_or_default()
+                .iter()
+                .map(|sibling| encoding.encode(sibling))
+                .collect(),
+        })
+        .collect();
+    ProofsFile {
+        root: encoding.encode(&tree.get_root().copied().unwrap_or_else(H::empty_root)),
+        leaf_count: leaves.len() as u64,
+        depth: MerkleTree::tree_depth(leaves.len()) as u64,
+        proofs,
+    }
+}
+
+fn read_csv(content: &str, column: Option<&str>) -> Result<Vec<String>, ProofsError> {
+    let mut rows = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, header)) = rows.next() else {
+        return Ok(vec![]);
+    };
+    let position = match column {
+        Some(column) => split_row(header)
+            .iter()
+            .position(|name| name == column)
+            .ok_or_else(|| ProofsError::MissingColumn(column.to_string()))?,
+        None => 0,
+    };
+    rows.map(|(line, row)| {
+        split_row(row)
+            .into_iter()
+            .nth(position)
+            .ok_or(ProofsError::ShortRow {
+                line: line + 1,
+                column: position,
+            })
+    })
+    .collect()
+}
+
+/// Splits a CSV row on commas outside of double quotes, `""` inside quotes is a
+/// literal quote.
+fn split_row(row: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.trim_end_matches('\r').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn read_json(content: &str, field: Option<&str>) -> Result<Vec<String>, ProofsError> {
+    let value: Value =
+        serde_json::from_str(content).map_err(|err| ProofsError::InvalidJson(err.to_string()))?;
+    let Value::Array(entries) = value else {
+        return Err(ProofsError::InvalidJson("expected an array".to_string()));
+    };
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let entry = match (entry, field) {
+                (Value::Object(object), Some(field)) => object
+                    .get(field)
+                    .ok_or_else(|| ProofsError::MissingColumn(field.to_string()))?,
+                (entry, _) => entry,
+            };
+            match entry {
+                Value::String(leaf) => Ok(leaf.clone()),
+                Value::Number(leaf) => Ok(leaf.to_string()),
+                _ => Err(ProofsError::InvalidEntry(index)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle_tree::{compute_root, leaf_hash, Keccak256Hasher, EMPTY_ROOT};
+
+    #[test]
+    fn test_read_leaves() {
+        let expected = vec!["alice,10".to_string(), "bob".to_string()];
+        assert_eq!(
+            read_leaves("alice,10\n\nbob\n", LeafFormat::Lines, None).unwrap(),
+            expected
+        );
+        assert_eq!(
+            read_leaves(r#"["alice,10", "bob"]"#, LeafFormat::Json, None).unwrap(),
+            expected
+        );
+
+        let csv = "name,amount\n\"alice,10\",10\r\n\"bob\",20\n";
+        assert_eq!(read_leaves(csv, LeafFormat::Csv, None).unwrap(), expected);
+        assert_eq!(
+            read_leaves(csv, LeafFormat::Csv, Some("amount")).unwrap(),
+            vec!["10", "20"]
+        );
+        assert_eq!(
+            read_leaves(csv, LeafFormat::Csv, Some("address")),
+            Err(ProofsError::MissingColumn("address".to_string()))
+        );
+
+        let json = r#"[{"address": "alice", "amount": 10}, {"address": "bob", "amount": 20}]"#;
+        assert_eq!(
+            read_leaves(json, LeafFormat::Json, Some("amount")).unwrap(),
+            vec!["10", "20"]
+        );
+        assert_eq!(
+            read_leaves(json, LeafFormat::Json, None),
+            Err(ProofsError::InvalidEntry(0))
+        );
+    }
+
+    #[test]
+    fn test_build_proofs() {
+        let leaves = ["Test-string1", "Test-string2", "Test-string3"];
+        let entries: Vec<_> = leaves.iter().map(|leaf| (leaf.to_string(), leaf)).collect();
+        let file = build_proofs(&entries);
+
+        let root = *MerkleTree::new(&leaves).get_root().unwrap();
+        assert_eq!(file.root, root.to_string());
+        assert_eq!(file.leaf_count, 3);
+        assert_eq!(file.depth, 2);
+        for (index, entry) in file.proofs.iter().enumerate() {
+            let siblings: Vec<Hash> = entry.proof.iter().map(|h| h.parse().unwrap()).collect();
+            assert_eq!(entry.leaf, leaves[index]);
+            assert_eq!(
+                compute_root(leaf_hash(leaves[index]), index, &siblings),
+                root
+            );
+        }
+
+        let empty = build_proofs::<&[u8]>(&[]);
+        assert_eq!(empty.root, EMPTY_ROOT.to_string());
+        assert!(empty.proofs.is_empty());
+
+        let keccak = build_proofs_with_hasher::<Keccak256Hasher, _>(&entries, HashEncoding::Hex);
+        let tree = MerkleTree::<Keccak256Hasher>::new_with_hasher(&leaves);
+        let root = tree.get_root().unwrap();
+        assert_eq!(keccak.root, format!("0x{}", hex::encode(root)));
+        assert_eq!(keccak.root.len(), 66);
+        assert_eq!(
+            keccak.proofs[2].proof[0],
+            HashEncoding::Hex.encode(&tree.find_path(2).unwrap().siblings()[0])
+        );
+    }
+}
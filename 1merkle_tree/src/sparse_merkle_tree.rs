@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use solana_program::hash::{hashv, Hash, HASH_BYTES};
+
+use crate::merkle_tree::{INTERMEDIATE_PREFIX, LEAF_PREFIX};
+
+/// Every key is a 256-bit hash, so every leaf sits 256 levels below the root.
+pub const SMT_DEPTH: usize = HASH_BYTES * 8;
+
+// Leaf slots that hold no value. Non-membership of a key is proven by showing that
+// its slot still holds this hash.
+const EMPTY_LEAF: Hash = Hash::new_from_array([0; HASH_BYTES]);
+
+fn hash_leaf(key: &Hash, value: &[u8]) -> Hash {
+    hashv(&[LEAF_PREFIX, key.as_ref(), value])
+}
+
+fn hash_intermediate(lsib: &Hash, rsib: &Hash) -> Hash {
+    hashv(&[INTERMEDIATE_PREFIX, lsib.as_ref(), rsib.as_ref()])
+}
+
+/// Roots of empty subtrees, `default_nodes()[height]` being the root of an empty
+/// subtree `height` levels high.
+pub fn default_nodes() -> Vec<Hash> {
+    let mut nodes = Vec::with_capacity(SMT_DEPTH + 1);
+    nodes.push(EMPTY_LEAF);
+    for height in 0..SMT_DEPTH {
+        nodes.push(hash_intermediate(&nodes[height], &nodes[height]));
+    }
+    nodes
+}
+
+// Bit `i` of the path to `key`, counted from the root.
+fn path_bit(key: &Hash, i: usize) -> bool {
+    key.as_ref()[i / 8] & (0x80 >> (i % 8)) != 0
+}
+
+// Key of the subtree `height` levels high that `key` is in: the path to `key` with
+// the last `height` bits cleared.
+fn subtree_prefix(key: &Hash, height: usize) -> Hash {
+    let mut prefix = key.to_bytes();
+    for i in (SMT_DEPTH - height)..SMT_DEPTH {
+        prefix[i / 8] &= !(0x80 >> (i % 8));
+    }
+    Hash::new_from_array(prefix)
+}
+
+// Key of the sibling of the subtree `height` levels high that `key` is in.
+fn sibling_prefix(key: &Hash, height: usize) -> Hash {
+    let mut prefix = subtree_prefix(key, height).to_bytes();
+    let i = SMT_DEPTH - 1 - height;
+    prefix[i / 8] ^= 0x80 >> (i % 8);
+    Hash::new_from_array(prefix)
+}
+
+/// Sparse Merkle tree over 256-bit keys that can prove both that a key is present
+/// with a given value and that a key is absent.
+///
+/// Only nodes that differ from the empty subtree of their height are stored, every
+/// other node is looked up in the cached default nodes.
+#[derive(Clone, Debug)]
+pub struct SparseMerkleTree {
+    default_nodes: Vec<Hash>,
+    // (height, subtree prefix) -> node hash, for non-default nodes only
+    nodes: BTreeMap<(usize, Hash), Hash>,
+}
+
+impl Default for SparseMerkleTree {
+    fn default() -> Self {
+        SparseMerkleTree::new()
+    }
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        SparseMerkleTree {
+            default_nodes: default_nodes(),
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    pub fn get_root(&self) -> Hash {
+        self.node(SMT_DEPTH, &Hash::default())
+    }
+
+    pub fn contains(&self, key: &Hash) -> bool {
+        self.nodes.contains_key(&(0, *key))
+    }
+
+    pub fn insert(&mut self, key: &Hash, value: &[u8]) {
+        self.update(key, hash_leaf(key, value));
+    }
+
+    pub fn remove(&mut self, key: &Hash) {
+        self.update(key, EMPTY_LEAF);
+    }
+
+    /// Proof for the slot of `key`. It proves membership if the key is in the tree
+    /// and non-membership otherwise.
+    pub fn find_path(&self, key: &Hash) -> SparseProof {
+        let mut proof = SparseProof::default();
+        for height in 0..SMT_DEPTH {
+            let sibling = self.node(height, &sibling_prefix(key, height));
+            if sibling != self.default_nodes[height] {
+                proof.non_default[height / 8] |= 1 << (height % 8);
+                proof.siblings.push(sibling);
+            }
+        }
+        proof
+    }
+
+    fn node(&self, height: usize, prefix: &Hash) -> Hash {
+        self.nodes
+            .get(&(height, *prefix))
+            .copied()
+            .unwrap_or(self.default_nodes[height])
+    }
+
+    fn set_node(&mut self, height: usize, prefix: Hash, node: Hash) {
+        if node == self.default_nodes[height] {
+            self.nodes.remove(&(height, prefix));
+        } else {
+            self.nodes.insert((height, prefix), node);
+        }
+    }
+
+    fn update(&mut self, key: &Hash, leaf: Hash) {
+        let mut node = leaf;
+        self.set_node(0, *key, node);
+        for height in 0..SMT_DEPTH {
+            let sibling = self.node(height, &sibling_prefix(key, height));
+            node = if path_bit(key, SMT_DEPTH - 1 - height) {
+                hash_intermediate(&sibling, &node)
+            } else {
+                hash_intermediate(&node, &sibling)
+            };
+            self.set_node(height + 1, subtree_prefix(key, height + 1), node);
+        }
+    }
+}
+
+/// Inclusion or exclusion proof for one key of a `SparseMerkleTree`.
+///
+/// Siblings equal to the empty subtree of their height are left out, so the proof
+/// stays small for sparse trees.
+#[derive(
+    BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, Default, PartialEq,
+)]
+pub struct SparseProof {
+    /// Bit `height` is set when the sibling on that level is included in `siblings`.
+    pub non_default: [u8; HASH_BYTES],
+    /// Non-default sibling hashes from the leaf level up to the root.
+    pub siblings: Vec<Hash>,
+}
+
+impl SparseProof {
+    /// Checks that `key` is in the tree with `value` under the trusted `root`.
+    pub fn verify_inclusion(&self, key: &Hash, value: &[u8], root: &Hash) -> bool {
+        self.compute_root(key, hash_leaf(key, value)) == Some(*root)
+    }
+
+    /// Checks that `key` is not in the tree with the trusted `root`.
+    pub fn verify_exclusion(&self, key: &Hash, root: &Hash) -> bool {
+        self.compute_root(key, EMPTY_LEAF) == Some(*root)
+    }
+
+    // Returns `None` if the proof does not have exactly one hash per set bit.
+    fn compute_root(&self, key: &Hash, leaf: Hash) -> Option<Hash> {
+        let mut siblings = self.siblings.iter();
+        let mut default_node = EMPTY_LEAF;
+        let mut node = leaf;
+        for height in 0..SMT_DEPTH {
+            let sibling = if self.non_default[height / 8] & (1 << (height % 8)) != 0 {
+                siblings.next()?
+            } else {
+                &default_node
+            };
+            node = if path_bit(key, SMT_DEPTH - 1 - height) {
+                hash_intermediate(sibling, &node)
+            } else {
+                hash_intermediate(&node, sibling)
+            };
+            default_node = hash_intermediate(&default_node, &default_node);
+        }
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::hash::hash;
+
+    const TEST: &[&[u8]] = &[b"my", b"very", b"eager", b"mother", b"just", b"served"];
+    const BAD: &[&[u8]] = &[b"bad", b"missing", b"false"];
+
+    fn key(data: &[u8]) -> Hash {
+        hash(data)
+    }
+
+    fn test_tree() -> SparseMerkleTree {
+        let mut smt = SparseMerkleTree::new();
+        for s in TEST {
+            smt.insert(&key(s), s);
+        }
+        smt
+    }
+
+    #[test]
+    fn test_empty_tree_root() {
+        let smt = SparseMerkleTree::new();
+        assert_eq!(smt.get_root(), default_nodes()[SMT_DEPTH]);
+    }
+
+    #[test]
+    fn test_root_is_order_independent() {
+        let mut smt = SparseMerkleTree::new();
+        for s in TEST.iter().rev() {
+            smt.insert(&key(s), s);
+        }
+        assert_eq!(smt.get_root(), test_tree().get_root());
+    }
+
+    #[test]
+    fn test_insert_overwrites_value() {
+        let mut smt = test_tree();
+        let root = smt.get_root();
+        smt.insert(&key(TEST[0]), b"other");
+        assert_ne!(smt.get_root(), root);
+        smt.insert(&key(TEST[0]), TEST[0]);
+        assert_eq!(smt.get_root(), root);
+    }
+
+    #[test]
+    fn test_remove_restores_root() {
+        let mut smt = SparseMerkleTree::new();
+        let empty_root = smt.get_root();
+        smt.insert(&key(TEST[0]), TEST[0]);
+        assert!(smt.contains(&key(TEST[0])));
+        smt.remove(&key(TEST[0]));
+        assert!(!smt.contains(&key(TEST[0])));
+        assert_eq!(smt.get_root(), empty_root);
+        assert!(smt.nodes.is_empty());
+    }
+
+    #[test]
+    fn test_inclusion_proof() {
+        let smt = test_tree();
+        let root = smt.get_root();
+        for s in TEST {
+            let proof = smt.find_path(&key(s));
+            assert!(proof.verify_inclusion(&key(s), s, &root));
+            assert!(!proof.verify_inclusion(&key(s), b"other", &root));
+            assert!(!proof.verify_exclusion(&key(s), &root));
+        }
+    }
+
+    #[test]
+    fn test_exclusion_proof() {
+        let smt = test_tree();
+        let root = smt.get_root();
+        for s in BAD {
+            let proof = smt.find_path(&key(s));
+            assert!(proof.verify_exclusion(&key(s), &root));
+            assert!(!proof.verify_inclusion(&key(s), s, &root));
+        }
+    }
+
+    #[test]
+    fn test_proof_is_compressed() {
+        let smt = test_tree();
+        let proof = smt.find_path(&key(TEST[0]));
+        // with a handful of keys only the levels close to the root have siblings
+        assert!(proof.siblings.len() < 2 * TEST.len());
+        let ones: u32 = proof.non_default.iter().map(|b| b.count_ones()).sum();
+        assert_eq!(ones as usize, proof.siblings.len());
+    }
+
+    #[test]
+    fn test_malformed_proof() {
+        let smt = test_tree();
+        let root = smt.get_root();
+        let proof = smt.find_path(&key(TEST[1]));
+
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert!(!short.verify_inclusion(&key(TEST[1]), TEST[1], &root));
+
+        let mut long = proof;
+        long.siblings.push(Hash::default());
+        assert!(!long.verify_inclusion(&key(TEST[1]), TEST[1], &root));
+    }
+
+    #[test]
+    fn test_proof_encoding() {
+        let smt = test_tree();
+        let proof = smt.find_path(&key(BAD[0]));
+        let bytes = borsh::to_vec(&proof).unwrap();
+        assert_eq!(SparseProof::try_from_slice(&bytes).unwrap(), proof);
+    }
+}
@@ -1,9 +1,421 @@
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::hash:: Hash;
+use bytemuck::{Pod, Zeroable};
+use solana_program::{
+    entrypoint::ProgramResult,
+    hash::{Hash, HASH_BYTES},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
 
+use crate::{
+    error::CustomError,
+    merkle_tree::{
+        frontier_append, frontier_push, frontier_root, leaf_hash, IncrementalMerkleTree, EMPTY_ROOT,
+    },
+};
+
+pub const VAULT_SEED: &[u8] = b"vault";
+pub const COUNTER_SEED: &[u8] = b"counter";
+
+/// Derives the vault of the tree `tree_id` created by `payer`. A payer can own any
+/// number of trees as long as their ids differ.
+pub fn find_vault_address(program_id: &Pubkey, payer: &Pubkey, tree_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[VAULT_SEED, payer.as_ref(), &tree_id.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// Derives the counter `counter_id` created by `payer`.
+pub fn find_counter_address(program_id: &Pubkey, payer: &Pubkey, counter_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[COUNTER_SEED, payer.as_ref(), &counter_id.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// Account size used by the clients when creating a vault.
+pub const DEFAULT_ACCOUNT_SPACE: u64 = 10 * 1024;
+
+/// Leaf hash stored in place of a removed leaf. No leaf data hashes to it.
+pub const REMOVED_LEAF: Hash = Hash::new_from_array([0; 32]);
+
+/// Written in front of the Borsh encoded state of every account.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct AccountHeader {
+    pub discriminator: [u8; 8],
+    pub version: u8,
+}
+
+pub const HEADER_LEN: usize = 9;
+
+/// State stored in a program account behind an `AccountHeader`.
+pub trait AccountState: BorshSerialize + BorshDeserialize {
+    /// Tells the account types apart, so one can't be passed in place of another.
+    const DISCRIMINATOR: [u8; 8];
+    /// Current layout version, older ones are upgraded by the `Migrate` instruction.
+    const VERSION: u8;
+
+    /// Canonical address and bump of the PDA, derived from the stored seeds.
+    fn find_address(&self, program_id: &Pubkey) -> (Pubkey, u8);
+    /// Bump stored when the account was created.
+    fn bump(&self) -> u8;
+
+    /// Parses the account data, failing on a wrong discriminator or version.
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let header = data
+            .get(..HEADER_LEN)
+            .and_then(|header| AccountHeader::try_from_slice(header).ok())
+            .ok_or(CustomError::AccountDataCorrupt)?;
+        if header.discriminator != Self::DISCRIMINATOR {
+            return Err(CustomError::InvalidAccountType.into());
+        }
+        if header.version != Self::VERSION {
+            return Err(CustomError::UnsupportedVersion.into());
+        }
+        solana_program::borsh1::try_from_slice_unchecked::<Self>(&data[HEADER_LEN..])
+            .map_err(|_| CustomError::AccountDataCorrupt.into())
+    }
+
+    /// Writes the header and the state, failing with `AccountFull` instead of an
+    /// opaque serialization error when they do not fit.
+    fn pack(&self, data: &mut [u8]) -> ProgramResult {
+        if self.packed_len()? > data.len() {
+            return Err(CustomError::AccountFull.into());
+        }
+        let header = AccountHeader {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+        };
+        let mut writer = data;
+        header.serialize(&mut writer)?;
+        self.serialize(&mut writer)?;
+        Ok(())
+    }
+
+    fn packed_len(&self) -> Result<usize, ProgramError> {
+        Ok(HEADER_LEN + borsh::object_length(self)?)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, ProgramError> {
+        let mut data = vec![0; self.packed_len()?];
+        self.pack(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Deepest tree the frontier of a `TreeAccount` can hold, far more leaves than fit
+/// in an account.
+pub const MAX_TREE_DEPTH: usize = 32;
+
+/// Most leaves a `TreeAccount` can hold. The frontier has no level left for the root
+/// of a complete tree of depth `MAX_TREE_DEPTH`.
+pub const MAX_LEAVES: u64 = (1 << MAX_TREE_DEPTH) - 1;
+
+/// Merkle tree stored in a vault, followed by a slot for each leaf hash up to the end
+/// of the account. Instructions cast the account data in place instead of
+/// deserializing it, so they only touch the bytes they change.
+///
+/// A new tree has no leaves and `merkle_tree::EMPTY_ROOT` as its root. Integers are
+/// stored as little endian byte arrays to keep the layout free of padding and
+/// alignment requirements.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TreeAccount {
+    // same place and meaning as in `AccountHeader`
+    pub discriminator: [u8; 8],
+    pub version: u8,
+    // seeds of the vault address, checked by every instruction
+    pub bump: u8,
+    has_tree_id: u8,
+    tree_id: [u8; 8],
+    pub creator: Pubkey,
+    // the only key allowed to modify the account
+    pub authority: Pubkey,
+    pub root_hash: Hash,
+    leaf_count: [u8; 8],
+    frontier: [Hash; MAX_TREE_DEPTH],
+}
+
+// SAFETY: every field is `Pod` with an alignment of 1, so the `repr(C)` struct has no
+// padding (checked below) and any bit pattern is valid.
+unsafe impl Zeroable for TreeAccount {}
+unsafe impl Pod for TreeAccount {}
+const _: () = assert!(TreeAccount::LEN == 19 + 3 * 32 + 8 + MAX_TREE_DEPTH * HASH_BYTES);
+
+impl TreeAccount {
+    pub const DISCRIMINATOR: [u8; 8] = TreeAccountV1::DISCRIMINATOR;
+    pub const VERSION: u8 = 2;
+    pub const LEN: usize = std::mem::size_of::<TreeAccount>();
+
+    /// Account size needed to store `capacity` leaves.
+    pub fn space(capacity: u64) -> u64 {
+        Self::LEN as u64 + capacity * HASH_BYTES as u64
+    }
+
+    /// Casts the account data into the header and the stored leaf hashes, failing on
+    /// a wrong discriminator or version.
+    pub fn load(data: &[u8]) -> Result<(&TreeAccount, &[Hash]), ProgramError> {
+        Self::check(data)?;
+        let (header, slots) = Self::split(data)?;
+        let header: &TreeAccount = bytemuck::from_bytes(header);
+        let leafs = slots
+            .get(..header.leaf_count() as usize)
+            .ok_or(CustomError::AccountDataCorrupt)?;
+        Ok((header, leafs))
+    }
+
+    /// Same as `load`, but returns every leaf slot of the account, including the free
+    /// ones after `leaf_count`.
+    pub fn load_mut(data: &mut [u8]) -> Result<(&mut TreeAccount, &mut [Hash]), ProgramError> {
+        Self::check(data)?;
+        let (header, slots) = Self::split_mut(data)?;
+        let header: &mut TreeAccount = bytemuck::from_bytes_mut(header);
+        if header.leaf_count() > slots.len() as u64 {
+            return Err(CustomError::AccountDataCorrupt.into());
+        }
+        Ok((header, slots))
+    }
+
+    /// Writes a tree holding `leafs` over the whole account data.
+    pub fn init(
+        data: &mut [u8],
+        creator: Pubkey,
+        tree_id: Option<u64>,
+        bump: u8,
+        authority: Pubkey,
+        leafs: &[Hash],
+    ) -> ProgramResult {
+        data.fill(0);
+        let (header, slots) = Self::split_mut(data)?;
+        let header: &mut TreeAccount = bytemuck::from_bytes_mut(header);
+        *header = TreeAccount {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            bump,
+            has_tree_id: tree_id.is_some().into(),
+            tree_id: tree_id.unwrap_or_default().to_le_bytes(),
+            creator,
+            authority,
+            root_hash: EMPTY_ROOT,
+            leaf_count: [0; 8],
+            frontier: [Hash::default(); MAX_TREE_DEPTH],
+        };
+        for leaf in leafs {
+            header.append(slots, *leaf)?;
+        }
+        Ok(())
+    }
+
+    pub fn tree_id(&self) -> Option<u64> {
+        (self.has_tree_id != 0).then(|| u64::from_le_bytes(self.tree_id))
+    }
+
+    pub fn leaf_count(&self) -> u64 {
+        u64::from_le_bytes(self.leaf_count)
+    }
+
+    /// Derives the canonical vault address and bump from the stored seeds.
+    pub fn find_vault_address(&self, program_id: &Pubkey) -> (Pubkey, u8) {
+        find_tree_vault_address(program_id, &self.creator, self.tree_id())
+    }
+
+    /// Stores `leaf_hash` in the next free slot and updates the root, failing with
+    /// `AccountFull` when every slot is taken and with `TreeFull` at `MAX_LEAVES`.
+    pub fn append(&mut self, slots: &mut [Hash], leaf_hash: Hash) -> ProgramResult {
+        let leaf_count = self.leaf_count();
+        if leaf_count >= MAX_LEAVES {
+            return Err(CustomError::TreeFull.into());
+        }
+        let slot = slots
+            .get_mut(leaf_count as usize)
+            .ok_or(CustomError::AccountFull)?;
+        *slot = leaf_hash;
+        self.root_hash = frontier_append(&mut self.frontier, leaf_count, leaf_hash);
+        self.leaf_count = (leaf_count + 1).to_le_bytes();
+        Ok(())
+    }
+
+    /// Stores as many of `leaf_hashes` as there are free slots, up to `MAX_LEAVES`, and
+    /// updates the root once at the end. Returns how many were stored.
+    pub fn append_many(&mut self, slots: &mut [Hash], leaf_hashes: &[Hash]) -> usize {
+        let leaf_count = self.leaf_count();
+        let free = slots.get_mut(leaf_count as usize..).unwrap_or_default();
+        let room = MAX_LEAVES.saturating_sub(leaf_count) as usize;
+        let accepted = leaf_hashes.len().min(free.len()).min(room);
+        free[..accepted].copy_from_slice(&leaf_hashes[..accepted]);
+        for (index, leaf) in (leaf_count..).zip(&leaf_hashes[..accepted]) {
+            frontier_push(&mut self.frontier, index, *leaf);
+        }
+        if let Some(last) = leaf_hashes[..accepted].last() {
+            let leaf_count = leaf_count + accepted as u64;
+            self.root_hash = frontier_root(&self.frontier, leaf_count, *last);
+            self.leaf_count = leaf_count.to_le_bytes();
+        }
+        accepted
+    }
+
+    /// Recomputes the frontier and the root from the stored leaves, after one of them
+    /// was replaced. The frontier only supports appends.
+    pub fn rebuild(&mut self, leafs: &[Hash]) {
+        self.frontier = [Hash::default(); MAX_TREE_DEPTH];
+        for (index, leaf) in leafs.iter().enumerate() {
+            frontier_push(&mut self.frontier, index as u64, *leaf);
+        }
+        self.root_hash = leafs.last().map_or(EMPTY_ROOT, |last| {
+            frontier_root(&self.frontier, leafs.len() as u64, *last)
+        });
+    }
+
+    /// Checks the header before the size, so accounts in an older, shorter layout
+    /// report `UnsupportedVersion`.
+    fn check(data: &[u8]) -> ProgramResult {
+        let header = data
+            .get(..HEADER_LEN)
+            .and_then(|header| AccountHeader::try_from_slice(header).ok())
+            .ok_or(CustomError::AccountDataCorrupt)?;
+        if header.discriminator != Self::DISCRIMINATOR {
+            return Err(CustomError::InvalidAccountType.into());
+        }
+        if header.version != Self::VERSION {
+            return Err(CustomError::UnsupportedVersion.into());
+        }
+        Ok(())
+    }
+
+    fn split(data: &[u8]) -> Result<(&[u8], &[Hash]), ProgramError> {
+        if data.len() < Self::LEN {
+            return Err(CustomError::AccountDataCorrupt.into());
+        }
+        let (header, slots) = data.split_at(Self::LEN);
+        let slots_len = slots.len() / HASH_BYTES * HASH_BYTES;
+        Ok((header, bytemuck::cast_slice(&slots[..slots_len])))
+    }
+
+    fn split_mut(data: &mut [u8]) -> Result<(&mut [u8], &mut [Hash]), ProgramError> {
+        if data.len() < Self::LEN {
+            return Err(CustomError::AccountFull.into());
+        }
+        let (header, slots) = data.split_at_mut(Self::LEN);
+        let slots_len = slots.len() / HASH_BYTES * HASH_BYTES;
+        Ok((header, bytemuck::cast_slice_mut(&mut slots[..slots_len])))
+    }
+}
+
+/// Borsh layout of tree accounts up to version 1, kept for `Migrate`.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct TreeAccountV1 {
+    pub creator: Pubkey,
+    pub tree_id: Option<u64>,
+    pub bump: u8,
+    pub authority: Pubkey,
+    pub root_hash: Hash,
+    pub tree: IncrementalMerkleTree,
+    pub leafs: Vec<Hash>,
+}
+
+impl AccountState for TreeAccountV1 {
+    const DISCRIMINATOR: [u8; 8] = *b"mrkltree";
+    const VERSION: u8 = 1;
+
+    fn find_address(&self, program_id: &Pubkey) -> (Pubkey, u8) {
+        find_tree_vault_address(program_id, &self.creator, self.tree_id)
+    }
+
+    fn bump(&self) -> u8 {
+        self.bump
+    }
+}
+
+// Legacy vaults predate tree ids and only use the creator as seed.
+fn find_tree_vault_address(
+    program_id: &Pubkey,
+    creator: &Pubkey,
+    tree_id: Option<u64>,
+) -> (Pubkey, u8) {
+    match tree_id {
+        Some(tree_id) => find_vault_address(program_id, creator, tree_id),
+        None => Pubkey::find_program_address(&[VAULT_SEED, creator.as_ref()], program_id),
+    }
+}
+
+/// Standalone counter, independent from any tree.
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct CounterAccount {
+    // seeds of the counter address, checked by every instruction
+    pub creator: Pubkey,
+    pub counter_id: u64,
+    pub bump: u8,
+    // the only key allowed to modify the account
+    pub authority: Pubkey,
+    pub count: i64,
+}
+
+impl AccountState for CounterAccount {
+    const DISCRIMINATOR: [u8; 8] = *b"counter\0";
+    const VERSION: u8 = 1;
+
+    fn find_address(&self, program_id: &Pubkey) -> (Pubkey, u8) {
+        self.find_counter_address(program_id)
+    }
+
+    fn bump(&self) -> u8 {
+        self.bump
+    }
+}
+
+impl CounterAccount {
+    /// Derives the canonical counter address and bump from the stored seeds.
+    pub fn find_counter_address(&self, program_id: &Pubkey) -> (Pubkey, u8) {
+        find_counter_address(program_id, &self.creator, self.counter_id)
+    }
+}
+
+/// Account layout used before leaves were stored as hashes, kept for `MigrateLeafs`.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct LegacyCounterAccount {
     pub count: i64,
     pub root_hash: Hash,
-    pub leafs: Vec<String> ,
+    pub leafs: Vec<String>,
+}
+
+impl LegacyCounterAccount {
+    /// Legacy vaults predate tree ids, so `tree_id` stays `None` and the address is
+    /// derived from the creator alone. The legacy count is dropped, counters live in
+    /// their own accounts now.
+    pub fn into_current(self, creator: Pubkey, bump: u8) -> TreeAccountV1 {
+        let leafs: Vec<Hash> = self.leafs.iter().map(leaf_hash).collect();
+        TreeAccountV1 {
+            creator,
+            tree_id: None,
+            bump,
+            authority: creator,
+            root_hash: self.root_hash,
+            tree: IncrementalMerkleTree::from_leaf_hashes(&leafs),
+            leafs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_full() {
+        let mut data = vec![0u8; TreeAccount::space(1) as usize];
+        let key = Pubkey::new_unique();
+        TreeAccount::init(&mut data, key, Some(0), 255, key, &[]).unwrap();
+        let (tree_data, slots) = TreeAccount::load_mut(&mut data).unwrap();
+
+        tree_data.append(slots, Hash::new_unique()).unwrap();
+        let err = tree_data.append(slots, Hash::new_unique()).unwrap_err();
+        assert_eq!(err, CustomError::AccountFull.into());
+
+        // the frontier runs out before any account could
+        tree_data.leaf_count = MAX_LEAVES.to_le_bytes();
+        let err = tree_data.append(slots, Hash::new_unique()).unwrap_err();
+        assert_eq!(err, CustomError::TreeFull.into());
+        assert_eq!(tree_data.append_many(slots, &[Hash::new_unique()]), 0);
+    }
 }
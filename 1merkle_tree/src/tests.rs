@@ -1,15 +1,22 @@
 // use borsh::BorshDeserialize;
+use borsh::{BorshDeserialize, BorshSerialize};
 use env_logger;
+use error::CustomError;
 use instructions::CounterInstruction;
+use solana_program::program_error::ProgramError;
 use solana_program_test::*;
 use solana_sdk::{
-    instruction::{AccountMeta, Instruction},
-    // signature::Keypair,
+    account::Account,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    signature::Keypair,
     signer::Signer,
     system_program,
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError},
+};
+use state::{
+    find_counter_address, find_vault_address, AccountState, CounterAccount, LegacyCounterAccount,
+    TreeAccount, TreeAccountV1, DEFAULT_ACCOUNT_SPACE,
 };
-use state::CounterAccount;
 
 use super::*;
 
@@ -19,7 +26,9 @@ fn panic_log(content: String) -> ! {
 }
 
 fn setup() {
-    env_logger::builder()
+    // ProgramTest installs its own logger, and tests run in parallel, so another one
+    // may already be set
+    let _ = env_logger::builder()
         .is_test(true)
         //disable all logs by defaykt
         .filter(None, log::LevelFilter::Off)
@@ -30,7 +39,12 @@ fn setup() {
         )
         //enable our test log
         .filter_module("counter_program::test", log::LevelFilter::Trace)
-        .init();
+        .try_init();
+}
+
+/// The error of a transaction whose first instruction failed with `error`.
+fn custom_error(error: CustomError) -> TransactionError {
+    TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
 }
 
 #[tokio::test]
@@ -61,14 +75,14 @@ async fn test_init() {
     // let bump_seed = 128u8;
     // let signers =&[&payer, &counter_keypair];
 
-    let (counter_keypair_pub, bump_seed) =
-        Pubkey::find_program_address(&[b"vault", payer.pubkey().as_ref()], &program_id);
+    let (counter_keypair_pub, bump_seed) = find_vault_address(&program_id, &payer.pubkey(), 0);
+    let (counter_pub, _) = find_counter_address(&program_id, &payer.pubkey(), 0);
     let signers = &[&payer];
 
     println!("Testing init...");
 
     // let init_val = 10i64;
-    let init_val: i64 = bump_seed.into();
+    let init_val = 0i64;
 
     // let mut init_instruction_data: Vec<u8> = vec![CounterInstruction::InitCounter as u8];
     // init_instruction_data.extend_from_slice(&init_val.to_le_bytes());
@@ -83,20 +97,13 @@ async fn test_init() {
     //     ],
     // );
 
-    let data = CounterInstruction::InitCounter(bump_seed);
-
-    let init_instruction = Instruction::new_with_borsh(
-        program_id,
-        &data,
-        vec![
-            // AccountMeta::new(counter_keypair_pub, true),
-            AccountMeta::new(counter_keypair_pub, false),
-            AccountMeta::new(payer.pubkey(), true),
-            AccountMeta::new_readonly(system_program::id(), false),
-        ],
-    );
+    let init_instruction = client::init_tree_ix(&program_id, &payer.pubkey(), 0);
+    let init_counter_instruction = client::init_counter_ix(&program_id, &payer.pubkey(), 0);
     // println!("Test1");
-    let mut tx = Transaction::new_with_payer(&[init_instruction], Some(&payer.pubkey()));
+    let mut tx = Transaction::new_with_payer(
+        &[init_instruction, init_counter_instruction],
+        Some(&payer.pubkey()),
+    );
     tx.sign(signers, recent_blockhash);
 
     // // tx.partial_sign(signers, recent_blockhash);
@@ -110,7 +117,17 @@ async fn test_init() {
     bank_clients.process_transaction(tx).await.unwrap();
     // println!("Test2");
 
-    let account = match bank_clients.get_account(counter_keypair_pub).await {
+    let account = bank_clients
+        .get_account(counter_keypair_pub)
+        .await
+        .unwrap()
+        .expect("No tree account found");
+    let (tree_data, leafs) = TreeAccount::load(&account.data).unwrap();
+    assert_eq!(tree_data.bump, bump_seed);
+    assert!(leafs.is_empty());
+    assert_eq!(tree_data.root_hash, merkle_tree::EMPTY_ROOT);
+
+    let account = match bank_clients.get_account(counter_pub).await {
         Ok(x) => x,
         Err(err) => {
             panic_log(format!("failed to get counter account: {}", err));
@@ -120,9 +137,7 @@ async fn test_init() {
     if let Some(account_data) = account {
         // let counter = CounterAccount::try_from_slice(&account_data.data)
         //     .expect("failed to deserialize counter data"); //глючит Custom { kind: InvalidData, error: "Not all bytes read" }
-        let counter =
-            solana_program::borsh1::try_from_slice_unchecked::<CounterAccount>(&account_data.data)
-                .unwrap();
+        let counter = CounterAccount::unpack(&account_data.data).unwrap();
         assert_eq!(counter.count, init_val);
         println!("counter init successfully with value {}", counter.count);
     } else {
@@ -137,24 +152,14 @@ async fn test_init() {
     //     vec![AccountMeta::new(counter_keypair_pub, true)],
     // );
 
-    let data = CounterInstruction::IncCounter;
-
-    let inc_instructionb = Instruction::new_with_borsh(
-        program_id,
-        &data,
-        vec![
-            // AccountMeta::new(counter_keypair_pub, true),
-            AccountMeta::new(counter_keypair_pub, false),
-            AccountMeta::new(payer.pubkey(), true),
-            AccountMeta::new_readonly(system_program::id(), false),
-        ],
-    );
+    let inc_instructionb =
+        client::change_counter_ix(&program_id, &counter_pub, &payer.pubkey(), true);
 
     let mut tx = Transaction::new_with_payer(&[inc_instructionb], Some(&payer.pubkey()));
     tx.sign(signers, recent_blockhash);
     bank_clients.process_transaction(tx).await.unwrap();
 
-    let account = match bank_clients.get_account(counter_keypair_pub).await {
+    let account = match bank_clients.get_account(counter_pub).await {
         Ok(x) => x,
         Err(err) => {
             panic_log(format!("failed to get counter account: {}", err));
@@ -164,9 +169,7 @@ async fn test_init() {
     if let Some(account_data) = account {
         // let counter = CounterAccount::try_from_slice(&account_data.data)
         //     .expect("failed to deserialize counter data");
-        let counter =
-            solana_program::borsh1::try_from_slice_unchecked::<CounterAccount>(&account_data.data)
-                .unwrap();
+        let counter = CounterAccount::unpack(&account_data.data).unwrap();
         assert_eq!(counter.count, init_val + 1);
         println!("counter incremented successfully to {}", counter.count);
     } else {
@@ -181,24 +184,14 @@ async fn test_init() {
     //     vec![AccountMeta::new(counter_keypair_pub, true)],
     // );
 
-    let data = CounterInstruction::DecCounter;
-
-    let dec_instruction = Instruction::new_with_borsh(
-        program_id,
-        &data,
-        vec![
-            // AccountMeta::new(counter_keypair_pub, true),
-            AccountMeta::new(counter_keypair_pub, false),
-            AccountMeta::new(payer.pubkey(), true),
-            AccountMeta::new_readonly(system_program::id(), false),
-        ],
-    );
+    let dec_instruction =
+        client::change_counter_ix(&program_id, &counter_pub, &payer.pubkey(), false);
 
     let mut tx = Transaction::new_with_payer(&[dec_instruction], Some(&payer.pubkey()));
     tx.sign(signers, recent_blockhash);
     bank_clients.process_transaction(tx).await.unwrap();
 
-    let account = match bank_clients.get_account(counter_keypair_pub).await {
+    let account = match bank_clients.get_account(counter_pub).await {
         Ok(x) => x,
         Err(err) => {
             panic_log(format!("failed to get counter account: {}", err));
@@ -208,9 +201,7 @@ async fn test_init() {
     if let Some(account_data) = account {
         // let counter = CounterAccount::try_from_slice(&account_data.data)
         //     .expect("failed to deserialize counter data");
-        let counter =
-            solana_program::borsh1::try_from_slice_unchecked::<CounterAccount>(&account_data.data)
-                .unwrap();
+        let counter = CounterAccount::unpack(&account_data.data).unwrap();
         assert_eq!(counter.count, init_val);
         println!("counter decremented successfully to {}", counter.count);
     } else {
@@ -225,18 +216,10 @@ async fn test_init() {
     //     vec![AccountMeta::new(counter_keypair_pub, true)],
     // );
 
-    let data = CounterInstruction::AddLeaf("Test-string1".to_string());
+    let init_str = "Test-string1";
 
-    let dec_instruction = Instruction::new_with_borsh(
-        program_id,
-        &data,
-        vec![
-            // AccountMeta::new(counter_keypair_pub, true),
-            AccountMeta::new(counter_keypair_pub, false),
-            AccountMeta::new(payer.pubkey(), true),
-            AccountMeta::new_readonly(system_program::id(), false),
-        ],
-    );
+    let dec_instruction =
+        client::add_leaf_ix(&program_id, &counter_keypair_pub, &payer.pubkey(), init_str);
 
     let mut tx = Transaction::new_with_payer(&[dec_instruction], Some(&payer.pubkey()));
     tx.sign(signers, recent_blockhash);
@@ -250,22 +233,19 @@ async fn test_init() {
     };
 
     if let Some(account_data) = account {
-        let mut counter_data =
-            solana_program::borsh1::try_from_slice_unchecked::<CounterAccount>(&account_data.data)
-                .unwrap();
+        let (counter_data, leafs) = TreeAccount::load(&account_data.data).unwrap();
+        let mut leafs = leafs.to_vec();
 
-        if let CounterInstruction::AddLeaf(init_str) = data {
-            println!("root_hash {}", counter_data.root_hash);
-            println!("leafs {:?}", counter_data.leafs);
+        println!("root_hash {}", counter_data.root_hash);
+        println!("leafs {:?}", leafs);
 
-            counter_data.leafs.pop();
-            counter_data.leafs.push(init_str);
+        leafs.pop();
+        leafs.push(merkle_tree::leaf_hash(init_str));
 
-            let tree = merkle_tree::MerkleTree::new(&counter_data.leafs);
+        let tree = merkle_tree::MerkleTree::from_leaf_hashes(&leafs);
 
-            let new_hash = *tree.get_root().unwrap();
-            assert_eq!(counter_data.root_hash, new_hash);
-        }
+        let new_hash = *tree.get_root().unwrap();
+        assert_eq!(counter_data.root_hash, new_hash);
     } else {
         panic_log(format!("No counter account found"));
     }
@@ -278,18 +258,10 @@ async fn test_init() {
     //     vec![AccountMeta::new(counter_keypair_pub, true)],
     // );
 
-    let data = CounterInstruction::AddLeaf("Test-string2".to_string());
+    let init_str = "Test-string2";
 
-    let dec_instruction = Instruction::new_with_borsh(
-        program_id,
-        &data,
-        vec![
-            // AccountMeta::new(counter_keypair_pub, true),
-            AccountMeta::new(counter_keypair_pub, false),
-            AccountMeta::new(payer.pubkey(), true),
-            AccountMeta::new_readonly(system_program::id(), false),
-        ],
-    );
+    let dec_instruction =
+        client::add_leaf_ix(&program_id, &counter_keypair_pub, &payer.pubkey(), init_str);
 
     let mut tx = Transaction::new_with_payer(&[dec_instruction], Some(&payer.pubkey()));
     tx.sign(signers, recent_blockhash);
@@ -303,25 +275,989 @@ async fn test_init() {
     };
 
     if let Some(account_data) = account {
-        let mut counter_data =
-            solana_program::borsh1::try_from_slice_unchecked::<CounterAccount>(&account_data.data)
-                .unwrap();
+        let (counter_data, leafs) = TreeAccount::load(&account_data.data).unwrap();
+        let mut leafs = leafs.to_vec();
 
-        if let CounterInstruction::AddLeaf(init_str) = data {
-            println!("root_hash {}", counter_data.root_hash);
-            println!("leafs {:?}", counter_data.leafs);
+        println!("root_hash {}", counter_data.root_hash);
+        println!("leafs {:?}", leafs);
 
-            counter_data.leafs.pop();
-            counter_data.leafs.push(init_str);
+        leafs.pop();
+        leafs.push(merkle_tree::leaf_hash(init_str));
 
-            let tree = merkle_tree::MerkleTree::new(&counter_data.leafs);
+        let tree = merkle_tree::MerkleTree::from_leaf_hashes(&leafs);
 
-            let new_hash = *tree.get_root().unwrap();
-            assert_eq!(counter_data.root_hash, new_hash);
-        }
+        let new_hash = *tree.get_root().unwrap();
+        assert_eq!(counter_data.root_hash, new_hash);
     } else {
         panic_log(format!("No counter account found"));
     }
 }
 
+#[tokio::test]
+async fn test_migrate_leafs() {
+    let program_id = Pubkey::new_unique();
+    let creator = Keypair::new();
+    let (counter_pubkey, _) =
+        Pubkey::find_program_address(&[b"vault", creator.pubkey().as_ref()], &program_id);
+
+    let legacy_leafs = vec!["Test-string1".to_string(), "Test-string2".to_string()];
+    let legacy_data = LegacyCounterAccount {
+        count: 7,
+        root_hash: *merkle_tree::MerkleTree::new(&legacy_leafs)
+            .get_root()
+            .unwrap(),
+        leafs: legacy_leafs.clone(),
+    };
+    let mut data = vec![0u8; 10 * 1024];
+    legacy_data.serialize(&mut &mut data[..]).unwrap();
+
+    let mut program_test =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints));
+    program_test.add_account(
+        counter_pubkey,
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: program_id,
+            ..Account::default()
+        },
+    );
+    let (mut bank_clients, payer, recent_blockhash) = program_test.start().await;
+
+    // only the creator of the vault can migrate it
+    let migrate_instruction = |creator_is_signer: bool| {
+        Instruction::new_with_borsh(
+            program_id,
+            &CounterInstruction::MigrateLeafs,
+            vec![
+                AccountMeta::new(counter_pubkey, false),
+                AccountMeta::new_readonly(creator.pubkey(), creator_is_signer),
+            ],
+        )
+    };
+    let mut tx = Transaction::new_with_payer(&[migrate_instruction(false)], Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    assert!(bank_clients.process_transaction(tx).await.is_err());
+
+    let mut tx = Transaction::new_with_payer(&[migrate_instruction(true)], Some(&payer.pubkey()));
+    tx.sign(&[&payer, &creator], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let account = bank_clients
+        .get_account(counter_pubkey)
+        .await
+        .unwrap()
+        .expect("No counter account found");
+    let (counter_data, leafs) = TreeAccount::load(&account.data).unwrap();
 
+    let expected_leafs: Vec<_> = legacy_leafs.iter().map(merkle_tree::leaf_hash).collect();
+    assert_eq!(counter_data.authority, creator.pubkey());
+    assert_eq!(counter_data.tree_id(), None);
+    assert_eq!(
+        counter_data.find_vault_address(&program_id),
+        (counter_pubkey, counter_data.bump)
+    );
+    assert_eq!(leafs, expected_leafs);
+    assert_eq!(
+        merkle_tree::MerkleTree::from_leaf_hashes(leafs).get_root(),
+        Some(&counter_data.root_hash)
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_placeholder_leafs() {
+    let program_id = Pubkey::new_unique();
+    let creator = Keypair::new();
+    let (vault, _) =
+        Pubkey::find_program_address(&[b"vault", creator.pubkey().as_ref()], &program_id);
+
+    // a legacy vault nobody added a leaf to still holds the init placeholders
+    let legacy_data = LegacyCounterAccount {
+        count: 0,
+        root_hash: solana_program::hash::Hash::new_from_array([1; 32]),
+        leafs: vec!["args".to_string(), "args".to_string(), "args!".to_string()],
+    };
+    let mut data = vec![0u8; 10 * 1024];
+    legacy_data.serialize(&mut &mut data[..]).unwrap();
+
+    let mut program_test =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints));
+    program_test.add_account(
+        vault,
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: program_id,
+            ..Account::default()
+        },
+    );
+    let (mut bank_clients, payer, recent_blockhash) = program_test.start().await;
+
+    let migrate_instruction = Instruction::new_with_borsh(
+        program_id,
+        &CounterInstruction::MigrateLeafs,
+        vec![
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(creator.pubkey(), true),
+        ],
+    );
+    let mut tx = Transaction::new_with_payer(&[migrate_instruction], Some(&payer.pubkey()));
+    tx.sign(&[&payer, &creator], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let account = bank_clients.get_account(vault).await.unwrap().unwrap();
+    let (tree_data, leafs) = TreeAccount::load(&account.data).unwrap();
+    assert!(leafs.is_empty());
+    assert_eq!(tree_data.root_hash, merkle_tree::EMPTY_ROOT);
+}
+
+#[tokio::test]
+async fn test_verify_leaf() {
+    let program_id = Pubkey::new_unique();
+    let (mut bank_clients, payer, recent_blockhash) =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints))
+            .start()
+            .await;
+
+    let counter_keypair_pub = client::vault_address(&program_id, &payer.pubkey(), 0);
+    let instructions = [
+        client::init_tree_ix(&program_id, &payer.pubkey(), 0),
+        client::add_leaf_ix(
+            &program_id,
+            &counter_keypair_pub,
+            &payer.pubkey(),
+            "Test-string1",
+        ),
+        client::add_leaf_ix(
+            &program_id,
+            &counter_keypair_pub,
+            &payer.pubkey(),
+            "Test-string2",
+        ),
+    ];
+    let mut tx = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let account = bank_clients
+        .get_account(counter_keypair_pub)
+        .await
+        .unwrap()
+        .expect("No counter account found");
+    let (_, leafs) = TreeAccount::load(&account.data).unwrap();
+    let tree = merkle_tree::MerkleTree::from_leaf_hashes(leafs);
+    let index = leafs.len() - 2;
+    let proof = tree.find_path(index).unwrap().siblings();
+
+    for (leaf, expected) in [("Test-string1", 1u8), ("Test-string2", 0u8)] {
+        let verify_instruction = client::verify_ix(
+            &program_id,
+            &counter_keypair_pub,
+            leaf,
+            index as u64,
+            proof.clone(),
+        );
+        let mut tx = Transaction::new_with_payer(&[verify_instruction], Some(&payer.pubkey()));
+        tx.sign(
+            &[&payer],
+            bank_clients.get_latest_blockhash().await.unwrap(),
+        );
+        let result = bank_clients
+            .process_transaction_with_metadata(tx)
+            .await
+            .unwrap();
+        assert!(result.result.is_ok());
+        let return_data = result.metadata.unwrap().return_data.unwrap();
+        assert_eq!(return_data.data, vec![expected]);
+    }
+}
+
+#[test]
+fn test_leaf_bytes_encoding() {
+    // clients built against the old `String` fields keep working
+    #[derive(BorshSerialize)]
+    enum OldInstruction {
+        VerifyLeaf {
+            leaf: String,
+            index: u64,
+            proof: Vec<solana_program::hash::Hash>,
+        },
+    }
+    let old = OldInstruction::VerifyLeaf {
+        leaf: "Test-string1".to_string(),
+        index: 1,
+        proof: vec![],
+    };
+    let new = CounterInstruction::VerifyLeaf {
+        leaf: b"Test-string1".to_vec(),
+        index: 1,
+        proof: vec![],
+    };
+    assert_eq!(
+        borsh::to_vec(&old).unwrap()[1..],
+        borsh::to_vec(&new).unwrap()[1..]
+    );
+}
+
+#[tokio::test]
+async fn test_add_leaf_bytes() {
+    let program_id = Pubkey::new_unique();
+    let (mut bank_clients, payer, recent_blockhash) =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints))
+            .start()
+            .await;
+
+    let (counter_keypair_pub, _) = find_vault_address(&program_id, &payer.pubkey(), 0);
+    let accounts = vec![
+        AccountMeta::new(counter_keypair_pub, false),
+        AccountMeta::new(payer.pubkey(), true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    // an airdrop claim, a pubkey and an amount
+    let claim = borsh::to_vec(&(Pubkey::new_unique(), 1_000u64)).unwrap();
+    let instructions = [
+        CounterInstruction::InitTree {
+            tree_id: 0,
+            space: DEFAULT_ACCOUNT_SPACE,
+        },
+        CounterInstruction::AddLeafBytes(claim.clone()),
+        CounterInstruction::AddLeafBytes(b"Test-string1".to_vec()),
+        CounterInstruction::AddLeaf("Test-string1".to_string()),
+    ]
+    .iter()
+    .map(|data| Instruction::new_with_borsh(program_id, data, accounts.clone()))
+    .collect::<Vec<_>>();
+    let mut tx = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let account = bank_clients
+        .get_account(counter_keypair_pub)
+        .await
+        .unwrap()
+        .expect("No counter account found");
+    let (tree_data, leafs) = TreeAccount::load(&account.data).unwrap();
+    assert_eq!(leafs[0], merkle_tree::leaf_hash(&claim));
+    assert_eq!(leafs[1], leafs[2]);
+    let tree = merkle_tree::MerkleTree::from_leaf_hashes(leafs);
+    assert_eq!(tree_data.root_hash, *tree.get_root().unwrap());
+
+    let verify_instruction = client::verify_ix(
+        &program_id,
+        &counter_keypair_pub,
+        claim,
+        0,
+        tree.find_path(0).unwrap().siblings(),
+    );
+    let mut tx = Transaction::new_with_payer(&[verify_instruction], Some(&payer.pubkey()));
+    tx.sign(
+        &[&payer],
+        bank_clients.get_latest_blockhash().await.unwrap(),
+    );
+    let result = bank_clients
+        .process_transaction_with_metadata(tx)
+        .await
+        .unwrap();
+    assert!(result.result.is_ok());
+    let return_data = result.metadata.unwrap().return_data.unwrap();
+    assert_eq!(return_data.data, vec![1]);
+}
+
+#[tokio::test]
+async fn test_update_and_remove_leaf() {
+    let program_id = Pubkey::new_unique();
+    let (mut bank_clients, payer, recent_blockhash) =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints))
+            .start()
+            .await;
+
+    let counter_keypair_pub = client::vault_address(&program_id, &payer.pubkey(), 0);
+    let authority = payer.pubkey();
+    let instructions = [
+        client::init_tree_ix(&program_id, &payer.pubkey(), 0),
+        client::add_leaves_ix(
+            &program_id,
+            &counter_keypair_pub,
+            &authority,
+            &["Test-string1", "Test-string2"],
+        ),
+        client::update_leaf_ix(
+            &program_id,
+            &counter_keypair_pub,
+            &authority,
+            0,
+            "Test-string3",
+        ),
+        client::remove_leaf_ix(&program_id, &counter_keypair_pub, &authority, 1),
+    ];
+    let mut tx = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let account = bank_clients
+        .get_account(counter_keypair_pub)
+        .await
+        .unwrap()
+        .expect("No counter account found");
+    let (counter_data, leafs) = TreeAccount::load(&account.data).unwrap();
+    assert_eq!(leafs.len(), 2);
+    assert_eq!(leafs[0], merkle_tree::leaf_hash("Test-string3"));
+    assert_eq!(leafs[1], state::REMOVED_LEAF);
+    let tree = merkle_tree::MerkleTree::from_leaf_hashes(leafs);
+    assert_eq!(tree.get_root(), Some(&counter_data.root_hash));
+
+    // out of bounds index
+    let remove_instruction =
+        client::remove_leaf_ix(&program_id, &counter_keypair_pub, &authority, 2);
+    let mut tx = Transaction::new_with_payer(&[remove_instruction], Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    assert!(bank_clients.process_transaction(tx).await.is_err());
+
+    // signed by someone other than the creator
+    let other = Keypair::new();
+    let update_instruction = client::update_leaf_ix(
+        &program_id,
+        &counter_keypair_pub,
+        &other.pubkey(),
+        0,
+        "Test-string4",
+    );
+    let mut tx = Transaction::new_with_payer(&[update_instruction], Some(&payer.pubkey()));
+    tx.sign(&[&payer, &other], recent_blockhash);
+    assert!(bank_clients.process_transaction(tx).await.is_err());
+}
+
+#[tokio::test]
+async fn test_set_authority() {
+    let program_id = Pubkey::new_unique();
+    let (mut bank_clients, payer, recent_blockhash) =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints))
+            .start()
+            .await;
+
+    let (counter_keypair_pub, _) = find_vault_address(&program_id, &payer.pubkey(), 0);
+    let new_authority = Keypair::new();
+
+    let init_instruction = client::init_tree_ix(&program_id, &payer.pubkey(), 0);
+    let set_authority_instruction = client::set_authority_ix(
+        &program_id,
+        &counter_keypair_pub,
+        &payer.pubkey(),
+        &new_authority.pubkey(),
+    );
+    let mut tx = Transaction::new_with_payer(
+        &[init_instruction, set_authority_instruction],
+        Some(&payer.pubkey()),
+    );
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let add_leaf_ix = |authority: &Pubkey| {
+        client::add_leaf_ix(&program_id, &counter_keypair_pub, authority, "Test-string1")
+    };
+
+    // the previous authority is rejected
+    let mut tx =
+        Transaction::new_with_payer(&[add_leaf_ix(&payer.pubkey())], Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    assert!(bank_clients.process_transaction(tx).await.is_err());
+
+    let mut tx = Transaction::new_with_payer(
+        &[add_leaf_ix(&new_authority.pubkey())],
+        Some(&payer.pubkey()),
+    );
+    tx.sign(&[&payer, &new_authority], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let account = bank_clients
+        .get_account(counter_keypair_pub)
+        .await
+        .unwrap()
+        .expect("No counter account found");
+    let (counter_data, leafs) = TreeAccount::load(&account.data).unwrap();
+    assert_eq!(counter_data.authority, new_authority.pubkey());
+    assert_eq!(leafs.len(), 1);
+
+    // counters hand over their authority the same way
+    let (counter, _) = find_counter_address(&program_id, &payer.pubkey(), 0);
+    let init_counter_instruction = Instruction::new_with_borsh(
+        program_id,
+        &CounterInstruction::InitCounter { counter_id: 0 },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    let set_authority_instruction = Instruction::new_with_borsh(
+        program_id,
+        &CounterInstruction::SetAuthority {
+            new_authority: new_authority.pubkey(),
+        },
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(payer.pubkey(), true),
+        ],
+    );
+    let mut tx = Transaction::new_with_payer(
+        &[init_counter_instruction, set_authority_instruction],
+        Some(&payer.pubkey()),
+    );
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let inc_instruction = Instruction::new_with_borsh(
+        program_id,
+        &CounterInstruction::IncCounter,
+        vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new_readonly(new_authority.pubkey(), true),
+        ],
+    );
+    let mut tx = Transaction::new_with_payer(&[inc_instruction], Some(&payer.pubkey()));
+    tx.sign(&[&payer, &new_authority], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let account = bank_clients.get_account(counter).await.unwrap().unwrap();
+    let counter_data = CounterAccount::unpack(&account.data).unwrap();
+    assert_eq!(counter_data.authority, new_authority.pubkey());
+    assert_eq!(counter_data.count, 1);
+}
+
+#[tokio::test]
+async fn test_init_errors() {
+    let program_id = Pubkey::new_unique();
+    let (mut bank_clients, payer, recent_blockhash) =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints))
+            .start()
+            .await;
+
+    // payer does not sign
+    let other = Keypair::new();
+    let (other_vault, _) = find_vault_address(&program_id, &other.pubkey(), 0);
+    let init_instruction = Instruction::new_with_borsh(
+        program_id,
+        &CounterInstruction::InitTree {
+            tree_id: 0,
+            space: DEFAULT_ACCOUNT_SPACE,
+        },
+        vec![
+            AccountMeta::new(other_vault, false),
+            AccountMeta::new(other.pubkey(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    let mut tx = Transaction::new_with_payer(&[init_instruction], Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::NotSigner));
+
+    // vault that is not derived from the payer
+    let (counter_keypair_pub, _) = find_vault_address(&program_id, &payer.pubkey(), 0);
+    let init_ix = |vault: Pubkey| {
+        Instruction::new_with_borsh(
+            program_id,
+            &CounterInstruction::InitTree {
+                tree_id: 0,
+                space: DEFAULT_ACCOUNT_SPACE,
+            },
+            vec![
+                AccountMeta::new(vault, false),
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        )
+    };
+    let mut tx = Transaction::new_with_payer(&[init_ix(other_vault)], Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::InvalidPda));
+
+    // the vault can only be initialized once
+    let mut tx =
+        Transaction::new_with_payer(&[init_ix(counter_keypair_pub)], Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    // same instruction again, so it needs a new blockhash to not be deduplicated
+    let new_blockhash = bank_clients
+        .get_new_latest_blockhash(&recent_blockhash)
+        .await
+        .unwrap();
+    let mut tx =
+        Transaction::new_with_payer(&[init_ix(counter_keypair_pub)], Some(&payer.pubkey()));
+    tx.sign(&[&payer], new_blockhash);
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::AlreadyInitialized));
+}
+
+#[tokio::test]
+async fn test_resize() {
+    let program_id = Pubkey::new_unique();
+    let (mut bank_clients, payer, recent_blockhash) =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints))
+            .start()
+            .await;
+
+    let counter_keypair_pub = client::vault_address(&program_id, &payer.pubkey(), 0);
+    let init_ix =
+        |space: u64| client::init_tree_with_space_ix(&program_id, &payer.pubkey(), 0, space);
+
+    // the initial state does not fit
+    let mut tx = Transaction::new_with_payer(&[init_ix(16)], Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::AccountFull));
+
+    let mut tx =
+        Transaction::new_with_payer(&[init_ix(TreeAccount::space(4))], Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let add_leaf_ix = |leaf: usize| {
+        client::add_leaf_ix(
+            &program_id,
+            &counter_keypair_pub,
+            &payer.pubkey(),
+            format!("Test-string{}", leaf),
+        )
+    };
+
+    // add leaves until every slot is taken
+    let mut leaf = 0;
+    let err = loop {
+        let mut tx = Transaction::new_with_payer(&[add_leaf_ix(leaf)], Some(&payer.pubkey()));
+        tx.sign(
+            &[&payer],
+            bank_clients.get_latest_blockhash().await.unwrap(),
+        );
+        if let Err(err) = bank_clients.process_transaction(tx).await {
+            break err;
+        }
+        leaf += 1;
+        assert!(leaf <= 4, "account never filled up");
+    };
+    assert_eq!(leaf, 4);
+    assert_eq!(err.unwrap(), custom_error(CustomError::AccountFull));
+
+    let resize_ix = client::resize_ix(
+        &program_id,
+        &counter_keypair_pub,
+        &payer.pubkey(),
+        &payer.pubkey(),
+        TreeAccount::space(8),
+    );
+    let mut tx =
+        Transaction::new_with_payer(&[resize_ix, add_leaf_ix(leaf)], Some(&payer.pubkey()));
+    tx.sign(
+        &[&payer],
+        bank_clients.get_latest_blockhash().await.unwrap(),
+    );
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let account = bank_clients
+        .get_account(counter_keypair_pub)
+        .await
+        .unwrap()
+        .expect("No counter account found");
+    assert_eq!(account.data.len() as u64, TreeAccount::space(8));
+    let rent = bank_clients.get_rent().await.unwrap();
+    assert_eq!(account.lamports, rent.minimum_balance(account.data.len()));
+    let (_, leafs) = TreeAccount::load(&account.data).unwrap();
+    assert_eq!(leafs.len(), leaf + 1);
+}
+
+#[tokio::test]
+async fn test_add_leaves() {
+    let program_id = Pubkey::new_unique();
+    let (mut bank_clients, payer, recent_blockhash) =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints))
+            .start()
+            .await;
+
+    let counter_keypair_pub = client::vault_address(&program_id, &payer.pubkey(), 0);
+    let init_ix =
+        client::init_tree_with_space_ix(&program_id, &payer.pubkey(), 0, TreeAccount::space(5));
+    let mut tx = Transaction::new_with_payer(&[init_ix], Some(&payer.pubkey()));
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let add_leaves_ix = |leaves: &[&str]| {
+        client::add_leaves_ix(&program_id, &counter_keypair_pub, &payer.pubkey(), leaves)
+    };
+
+    // the last leaf of the second batch doesn't fit
+    let batches: [&[&str]; 3] = [
+        &["Test-string1", "Test-string2", "Test-string3"],
+        &["Test-string4", "Test-string5", "Test-string6"],
+        &["Test-string7"],
+    ];
+    // the native harness prints `sol_log_data` instead of logging it, so the events
+    // are covered by the unit tests of `events` and `indexer`
+    let mut added = vec![];
+    for (batch, expected) in batches[..2].iter().zip([3u64, 2]) {
+        let mut tx = Transaction::new_with_payer(&[add_leaves_ix(batch)], Some(&payer.pubkey()));
+        tx.sign(
+            &[&payer],
+            bank_clients.get_latest_blockhash().await.unwrap(),
+        );
+        let result = bank_clients
+            .process_transaction_with_metadata(tx)
+            .await
+            .unwrap();
+        assert!(result.result.is_ok());
+        let metadata = result.metadata.unwrap();
+        assert_eq!(metadata.return_data.unwrap().data, expected.to_le_bytes());
+        added.extend_from_slice(&batch[..expected as usize]);
+    }
+
+    let account = bank_clients
+        .get_account(counter_keypair_pub)
+        .await
+        .unwrap()
+        .expect("No counter account found");
+    let (tree_data, leafs) = TreeAccount::load(&account.data).unwrap();
+    let tree = merkle_tree::MerkleTree::new(&added);
+    assert_eq!(leafs.len(), 5);
+    assert_eq!(tree_data.root_hash, *tree.get_root().unwrap());
+    let indexer = indexer::Indexer::from_account_data(program_id, &account.data).unwrap();
+    assert_eq!(indexer.leaves(), leafs);
+    assert_eq!(indexer.tree_address(), &counter_keypair_pub);
+
+    let mut tx = Transaction::new_with_payer(&[add_leaves_ix(batches[2])], Some(&payer.pubkey()));
+    tx.sign(
+        &[&payer],
+        bank_clients.get_latest_blockhash().await.unwrap(),
+    );
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::AccountFull));
+}
+
+#[tokio::test]
+async fn test_multiple_trees() {
+    let program_id = Pubkey::new_unique();
+    let (mut bank_clients, payer, recent_blockhash) =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints))
+            .start()
+            .await;
+
+    let vaults: Vec<_> = (0..2)
+        .map(|tree_id| {
+            (
+                tree_id,
+                find_vault_address(&program_id, &payer.pubkey(), tree_id),
+            )
+        })
+        .collect();
+    assert_ne!(vaults[0].1 .0, vaults[1].1 .0);
+
+    let init_instructions: Vec<_> = vaults
+        .iter()
+        .map(|&(tree_id, _)| client::init_tree_ix(&program_id, &payer.pubkey(), tree_id))
+        .collect();
+    let add_leaf_instruction = client::add_leaf_ix(
+        &program_id,
+        &vaults[1].1 .0,
+        &payer.pubkey(),
+        "Test-string1",
+    );
+    let mut tx = Transaction::new_with_payer(
+        &[init_instructions, vec![add_leaf_instruction]].concat(),
+        Some(&payer.pubkey()),
+    );
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    for (tree_id, leaf_count) in [(0, 0), (1, 1)] {
+        let account = bank_clients
+            .get_account(vaults[tree_id].1 .0)
+            .await
+            .unwrap()
+            .expect("No counter account found");
+        let (_, leafs) = TreeAccount::load(&account.data).unwrap();
+        assert_eq!(leafs.len(), leaf_count);
+    }
+}
+
+#[tokio::test]
+async fn test_non_canonical_vault() {
+    let program_id = Pubkey::new_unique();
+    let payer_key = Keypair::new();
+    let (_, canonical_bump) = find_vault_address(&program_id, &payer_key.pubkey(), 0);
+    let (vault, bump) = (0..canonical_bump)
+        .rev()
+        .find_map(|bump| {
+            Pubkey::create_program_address(
+                &[
+                    state::VAULT_SEED,
+                    payer_key.pubkey().as_ref(),
+                    &0u64.to_le_bytes(),
+                    &[bump],
+                ],
+                &program_id,
+            )
+            .ok()
+            .map(|vault| (vault, bump))
+        })
+        .unwrap();
+
+    // a vault at a valid but non-canonical address, as the old caller-provided bump allowed
+    let mut data = vec![0; DEFAULT_ACCOUNT_SPACE as usize];
+    TreeAccount::init(
+        &mut data,
+        payer_key.pubkey(),
+        Some(0),
+        bump,
+        payer_key.pubkey(),
+        &[],
+    )
+    .unwrap();
+    let mut program_test =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints));
+    program_test.add_account(
+        vault,
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: program_id,
+            ..Account::default()
+        },
+    );
+    let (mut bank_clients, payer, recent_blockhash) = program_test.start().await;
+
+    let inc_instruction =
+        client::add_leaf_ix(&program_id, &vault, &payer_key.pubkey(), "Test-string1");
+    let mut tx = Transaction::new_with_payer(&[inc_instruction], Some(&payer.pubkey()));
+    tx.sign(&[&payer, &payer_key], recent_blockhash);
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::InvalidPda));
+}
+
+#[tokio::test]
+async fn test_migrate() {
+    let program_id = Pubkey::new_unique();
+    let creator = Keypair::new();
+    let (vault, vault_bump) = find_vault_address(&program_id, &creator.pubkey(), 0);
+    let (vault_v1, vault_v1_bump) = find_vault_address(&program_id, &creator.pubkey(), 1);
+    let (counter, counter_bump) = find_counter_address(&program_id, &creator.pubkey(), 0);
+
+    let leafs: Vec<_> = ["Test-string1", "Test-string2"]
+        .iter()
+        .map(merkle_tree::leaf_hash)
+        .collect();
+    let tree_v1 = |tree_id: u64, bump: u8| TreeAccountV1 {
+        creator: creator.pubkey(),
+        tree_id: Some(tree_id),
+        bump,
+        authority: creator.pubkey(),
+        root_hash: merkle_tree::MerkleTree::from_leaf_hashes(&leafs)
+            .get_root()
+            .copied()
+            .unwrap(),
+        tree: merkle_tree::IncrementalMerkleTree::from_leaf_hashes(&leafs),
+        leafs: leafs.clone(),
+    };
+    let counter_data = CounterAccount {
+        creator: creator.pubkey(),
+        counter_id: 0,
+        bump: counter_bump,
+        authority: creator.pubkey(),
+        count: 5,
+    };
+    let rent = solana_sdk::rent::Rent::default();
+    let mut program_test =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints));
+    for (address, data) in [
+        // accounts written before the header existed, allocated to the exact state size
+        (vault, borsh::to_vec(&tree_v1(0, vault_bump)).unwrap()),
+        (counter, borsh::to_vec(&counter_data).unwrap()),
+        // a version 1 tree, with a header but still in the Borsh layout
+        (vault_v1, tree_v1(1, vault_v1_bump).to_bytes().unwrap()),
+    ] {
+        program_test.add_account(
+            address,
+            Account {
+                lamports: rent.minimum_balance(data.len()),
+                data,
+                owner: program_id,
+                ..Account::default()
+            },
+        );
+    }
+    program_test.add_account(
+        creator.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            ..Account::default()
+        },
+    );
+    let (mut bank_clients, payer, recent_blockhash) = program_test.start().await;
+
+    let add_leaf_ix = |account: Pubkey| {
+        client::add_leaf_ix(&program_id, &account, &creator.pubkey(), "Test-string3")
+    };
+
+    // without a header the data does not start with a known discriminator
+    let mut tx = Transaction::new_with_payer(&[add_leaf_ix(vault)], Some(&payer.pubkey()));
+    tx.sign(&[&payer, &creator], recent_blockhash);
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::InvalidAccountType));
+
+    // a version 1 tree has to be migrated to the zero-copy layout first
+    let mut tx = Transaction::new_with_payer(&[add_leaf_ix(vault_v1)], Some(&payer.pubkey()));
+    tx.sign(
+        &[&payer, &creator],
+        bank_clients.get_latest_blockhash().await.unwrap(),
+    );
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::UnsupportedVersion));
+
+    let migrate_instructions: Vec<_> = [vault, vault_v1, counter]
+        .iter()
+        .map(|account| client::migrate_ix(&program_id, account, &creator.pubkey()))
+        .collect();
+    let mut tx = Transaction::new_with_payer(&migrate_instructions, Some(&payer.pubkey()));
+    tx.sign(
+        &[&payer, &creator],
+        bank_clients.get_latest_blockhash().await.unwrap(),
+    );
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    for address in [vault, vault_v1] {
+        let account = bank_clients.get_account(address).await.unwrap().unwrap();
+        assert_eq!(
+            account.data.len() as u64,
+            TreeAccount::space(leafs.len() as u64)
+        );
+        assert_eq!(account.lamports, rent.minimum_balance(account.data.len()));
+        let (tree_data, migrated_leafs) = TreeAccount::load(&account.data).unwrap();
+        assert_eq!(migrated_leafs, &leafs[..]);
+        assert_eq!(
+            tree_data.root_hash,
+            *merkle_tree::MerkleTree::from_leaf_hashes(&leafs)
+                .get_root()
+                .unwrap()
+        );
+    }
+
+    let account = bank_clients.get_account(counter).await.unwrap().unwrap();
+    assert_eq!(account.lamports, rent.minimum_balance(account.data.len()));
+    let counter_data = CounterAccount::unpack(&account.data).unwrap();
+    assert_eq!(counter_data.count, 5);
+
+    // the migrated account is sized for the existing leaves only
+    let mut tx = Transaction::new_with_payer(&[add_leaf_ix(vault)], Some(&payer.pubkey()));
+    tx.sign(
+        &[&payer, &creator],
+        bank_clients.get_latest_blockhash().await.unwrap(),
+    );
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::AccountFull));
+
+    // a counter can't be passed in place of a tree
+    let mut tx = Transaction::new_with_payer(&[add_leaf_ix(counter)], Some(&payer.pubkey()));
+    tx.sign(
+        &[&payer, &creator],
+        bank_clients.get_latest_blockhash().await.unwrap(),
+    );
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(err.unwrap(), custom_error(CustomError::InvalidAccountType));
+}
+
+const CALLER_SEED: &[u8] = b"caller";
+
+/// Stands in for another on-chain program that owns a tree through a PDA and calls
+/// it with the `cpi` helpers. Takes a Borsh `(op, leaf, proof)` as data.
+fn caller_entrypoint(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let [merkle_program, vault, authority] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    let (op, leaf, proof) = <(u8, Vec<u8>, Vec<solana_program::hash::Hash>)>::try_from_slice(data)?;
+    let (_, bump) = Pubkey::find_program_address(&[CALLER_SEED], program_id);
+    let seeds: &[&[u8]] = &[CALLER_SEED, &[bump]];
+    match op {
+        0 => cpi::add_leaf(merkle_program, vault, authority, &leaf, &[seeds]),
+        1 => match cpi::add_leaves(merkle_program, vault, authority, &[leaf], &[seeds])? {
+            1 => Ok(()),
+            _ => Err(ProgramError::InvalidArgument),
+        },
+        _ => match cpi::verify_leaf(merkle_program, vault, leaf, 0, proof)? {
+            true => Ok(()),
+            false => Err(ProgramError::InvalidArgument),
+        },
+    }
+}
+
+#[tokio::test]
+async fn test_cpi() {
+    let program_id = Pubkey::new_unique();
+    let caller_id = Pubkey::new_unique();
+    let (caller_authority, _) = Pubkey::find_program_address(&[CALLER_SEED], &caller_id);
+    let mut program_test =
+        ProgramTest::new(env!("CARGO_PKG_NAME"), program_id, processor!(entrypoints));
+    program_test.add_program("caller", caller_id, processor!(caller_entrypoint));
+    let (mut bank_clients, payer, recent_blockhash) = program_test.start().await;
+
+    // hand the tree over to the caller
+    let vault = client::vault_address(&program_id, &payer.pubkey(), 0);
+    let mut tx = Transaction::new_with_payer(
+        &[
+            client::init_tree_ix(&program_id, &payer.pubkey(), 0),
+            client::set_authority_ix(&program_id, &vault, &payer.pubkey(), &caller_authority),
+        ],
+        Some(&payer.pubkey()),
+    );
+    tx.sign(&[&payer], recent_blockhash);
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let caller_ix = |op: u8, leaf: &str, proof: Vec<solana_program::hash::Hash>| {
+        Instruction::new_with_borsh(
+            caller_id,
+            &(op, leaf.as_bytes().to_vec(), proof),
+            vec![
+                AccountMeta::new_readonly(program_id, false),
+                AccountMeta::new(vault, false),
+                AccountMeta::new_readonly(caller_authority, false),
+            ],
+        )
+    };
+    let proof = vec![merkle_tree::leaf_hash("Test-string2")];
+    let mut tx = Transaction::new_with_payer(
+        &[
+            caller_ix(0, "Test-string1", vec![]),
+            caller_ix(1, "Test-string2", vec![]),
+            caller_ix(2, "Test-string1", proof.clone()),
+        ],
+        Some(&payer.pubkey()),
+    );
+    tx.sign(
+        &[&payer],
+        bank_clients.get_latest_blockhash().await.unwrap(),
+    );
+    bank_clients.process_transaction(tx).await.unwrap();
+
+    let account = bank_clients.get_account(vault).await.unwrap().unwrap();
+    let (_, leafs) = TreeAccount::load(&account.data).unwrap();
+    assert_eq!(
+        leafs,
+        [
+            merkle_tree::leaf_hash("Test-string1"),
+            merkle_tree::leaf_hash("Test-string2")
+        ]
+    );
+
+    let mut tx = Transaction::new_with_payer(
+        &[caller_ix(2, "Test-string3", proof)],
+        Some(&payer.pubkey()),
+    );
+    tx.sign(
+        &[&payer],
+        bank_clients.get_latest_blockhash().await.unwrap(),
+    );
+    let err = bank_clients.process_transaction(tx).await.unwrap_err();
+    assert_eq!(
+        err.unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidArgument)
+    );
+}